@@ -94,6 +94,9 @@ where
     let mut cmd = process::Command::new("protoc");
     let current_dir = out_dir_path.to_path_buf();
     cmd.arg("-I").arg(out_dir_path.to_path_buf());
+    for include in includes {
+        cmd.arg("-I").arg(include.as_ref());
+    }
     for proto in protos {
         let f = proto
             .as_ref()
@@ -15,9 +15,13 @@
 #include <google/protobuf/compiler/command_line_interface.h>
 #include <google/protobuf/compiler/plugin.h>
 #include <google/protobuf/descriptor.h>
+#include <google/protobuf/descriptor.pb.h>
 #include <google/protobuf/io/printer.h>
 #include <google/protobuf/io/zero_copy_stream.h>
 
+#include <map>
+#include <string>
+
 inline bool HasSuffixString(const std::string &str, const std::string &suffix) {
   return str.size() >= suffix.size() &&
          str.compare(str.size() - suffix.size(), suffix.size(), suffix) == 0;
@@ -40,6 +44,47 @@ inline std::string StripBeforeSlashFromRight(const std::string &str) {
   return str;
 }
 
+inline std::string DotsToColons(const std::string &name) {
+  std::string result;
+  for (size_t i = 0; i < name.size(); ++i) {
+    if (name[i] == '.') {
+      result += "::";
+    } else {
+      result += name[i];
+    }
+  }
+  return result;
+}
+
+// Fully qualified name of the C++ class generated by protoc for `descriptor`
+inline std::string ClassName(const google::protobuf::Descriptor *descriptor) {
+  std::string name = descriptor->name();
+  for (const google::protobuf::Descriptor *outer =
+           descriptor->containing_type();
+       outer != NULL; outer = outer->containing_type()) {
+    name = outer->name() + "_" + name;
+  }
+  const std::string &package = descriptor->file()->package();
+  if (package.empty()) {
+    return "::" + name;
+  }
+  return "::" + DotsToColons(package) + "::" + name;
+}
+
+// Add `file` and the files it imports to `pool`
+inline void AddToPool(const google::protobuf::FileDescriptor *file,
+                      google::protobuf::DescriptorPool *pool) {
+  if (pool->FindFileByName(file->name()) != NULL) {
+    return;
+  }
+  for (int i = 0; i < file->dependency_count(); ++i) {
+    AddToPool(file->dependency(i), pool);
+  }
+  google::protobuf::FileDescriptorProto file_proto;
+  file->CopyTo(&file_proto);
+  pool->BuildFile(file_proto);
+}
+
 namespace brpc_rs {
 class BrpcToProtobuf : public google::protobuf::compiler::CodeGenerator {
 public:
@@ -63,29 +108,26 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
 
   /* Generate *.proto */
 
+  // The original messages and services are kept as they are so that the
+  // services speak the same wire format as any other BRPC peer. Only C++
+  // generic services are switched on, as BRPC requires.
   google::protobuf::io::ZeroCopyOutputStream *proto_file =
       ctx->Open(proto_file_name);
   google::protobuf::io::Printer proto_printer(proto_file, '$');
-  proto_printer.Print("syntax=\"proto2\";\n");
-  proto_printer.Print("package $package_name$;\n\n", "package_name",
-                      file->package());
-
-  proto_printer.Print("option cc_generic_services = true;\n\n"
-                      "message HttpRequest {};\n"
-                      "message HttpResponse {};\n\n");
-
-  for (int i = 0; i < file->service_count(); ++i) {
-    const google::protobuf::ServiceDescriptor *service = file->service(i);
-    proto_printer.Print("service $service_name$ {\n", "service_name",
-                        service->name());
-    for (int j = 0; j < service->method_count(); ++j) {
-      const google::protobuf::MethodDescriptor *method = service->method(j);
-      proto_printer.Print(
-          "  rpc $method_name$(HttpRequest) returns (HttpResponse);\n",
-          "method_name", method->name());
-    }
-    proto_printer.Print("}\n");
+  google::protobuf::DescriptorPool pool;
+  for (int i = 0; i < file->dependency_count(); ++i) {
+    AddToPool(file->dependency(i), &pool);
+  }
+  google::protobuf::FileDescriptorProto file_proto;
+  file->CopyTo(&file_proto);
+  file_proto.mutable_options()->set_cc_generic_services(true);
+  const google::protobuf::FileDescriptor *generic_file =
+      pool.BuildFile(file_proto);
+  if (generic_file == NULL) {
+    *error = "failed to enable cc_generic_services in " + file->name();
+    return false;
   }
+  proto_printer.PrintRaw(generic_file->DebugString());
 
   /* Generate *.brpc.cc */
 
@@ -97,8 +139,7 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
   cpp_printer.Print("#include <brpc/server.h>\n"
                     "#include <brpc/channel.h>\n"
                     "#include <butil/logging.h>\n"
-                    "#include <butil/iobuf.h>\n\n"
-                    "#include <brpc/restful.h>\n\n");
+                    "#include <butil/iobuf.h>\n\n");
 
   cpp_printer.Print(
       "namespace butil {\n"
@@ -133,7 +174,8 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
       "public:\n"
       "  explicit ZeroCopyBufMut(IOBuf &buf)\n"
       "      : _block_start(NULL), _block_end(NULL), _total_len(0), "
-      "_stream(&buf) {\n"
+      "_buf(&buf),\n"
+      "        _stream(&buf) {\n"
       "    char *block_end = NULL;\n"
       "    int block_len;\n"
       "    if (_block_start == NULL) {\n"
@@ -149,10 +191,61 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
       "  char *_block_start;\n"
       "  char *_block_end;\n"
       "  uint64_t _total_len;\n"
+      "  IOBuf *_buf;\n"
       "  IOBufAsZeroCopyOutputStream _stream;\n"
       "};\n"
       "\n"
-      "} // namespace butil\n\n\n");
+      "} // namespace butil\n\n");
+
+  // Messages are handed over between C++ and Rust (prost) as length-delimited
+  // bytes in an IOBuf. The helpers are defined in brpc-sys, as is
+  // `brpc_call_method()`, which backs off the retries of the calls of stubs.
+  cpp_printer.Print(
+      "extern \"C\" {\n"
+      "int brpc_message_serialize_delimited(\n"
+      "    const google::protobuf::Message *msg, butil::IOBuf &buf);\n"
+      "int brpc_message_parse_delimited(google::protobuf::Message *msg,\n"
+      "                                 const butil::IOBuf &buf);\n"
+      "void brpc_call_method(google::protobuf::RpcChannel *channel,\n"
+      "                      const google::protobuf::MethodDescriptor "
+      "*method,\n"
+      "                      brpc::Controller *cntl,\n"
+      "                      const google::protobuf::Message *request,\n"
+      "                      google::protobuf::Message *response,\n"
+      "                      google::protobuf::Closure *done);\n"
+      "}\n\n");
+
+  cpp_printer.Print(
+      "namespace {\n"
+      "// Owns the messages of an asynchronous RPC and notifies Rust when the\n"
+      "// RPC is done.\n"
+      "template <typename Request, typename Response>\n"
+      "class AsyncCallDone : public google::protobuf::Closure {\n"
+      "public:\n"
+      "  AsyncCallDone(brpc::Controller *cntl, butil::IOBuf *response_buf,\n"
+      "                void (*callback)(void *), void *arg)\n"
+      "      : _cntl(cntl), _response_buf(response_buf), "
+      "_callback(callback),\n"
+      "        _arg(arg) {}\n"
+      "  void Run() {\n"
+      "    if (!_cntl->Failed() &&\n"
+      "        !brpc_message_serialize_delimited(&response, *_response_buf)) {\n"
+      "      _cntl->SetFailed(brpc::ERESPONSE, \"brpc-rs failed to pass "
+      "response\");\n"
+      "    }\n"
+      "    _callback(_arg);\n"
+      "    delete this;\n"
+      "  }\n"
+      "  Request request;\n"
+      "  Response response;\n"
+      "\n"
+      "private:\n"
+      "  brpc::Controller *_cntl;\n"
+      "  butil::IOBuf *_response_buf;\n"
+      "  void (*_callback)(void *);\n"
+      "  void *_arg;\n"
+      "};\n"
+      "} // namespace\n\n\n");
 
   // typedefs
   for (int i = 0; i < file->service_count(); ++i) {
@@ -190,33 +283,40 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
       const google::protobuf::MethodDescriptor *method = service->method(j);
       cpp_printer.Print(
           "  void $method_name$(google::protobuf::RpcController *cntl_base,\n"
-          "                     const HttpRequest *,\n"
-          "                     HttpResponse *, \n"
+          "                     const $input$ *request,\n"
+          "                     $output$ *response,\n"
           "                     google::protobuf::Closure *done) {\n"
           "    brpc::ClosureGuard done_guard(done);\n"
           "    brpc::Controller *cntl = \n"
           "        static_cast<brpc::Controller *>(cntl_base);\n"
-          "    cntl->http_response()\n"
-          "        .set_content_type(\"application/octet-stream\");\n"
-          "    butil::IOBuf &request_buf = cntl->request_attachment();\n"
-          "    butil::IOBuf &response_buf = cntl->response_attachment();\n"
-          "    butil::ZeroCopyBuf zc_request(request_buf);\n"
-          "    butil::ZeroCopyBufMut zc_response(response_buf);\n"
-          "    if (0 != $method_name$_trampoline(\n"
-          "               $method_name$_closure_ptr,\n"
-          "               zc_request,\n"
-          "               zc_response)) {\n"
-          "      cntl->SetFailed(brpc::EINTERNAL, \"brpc-rs controller "
-          "failed\");\n"
+          "    if ($method_name$_trampoline == NULL) {\n"
+          "      cntl->SetFailed(brpc::ENOMETHOD, \"brpc-rs handler not set\");\n"
+          "      return;\n"
           "    }\n"
+          "    butil::IOBuf request_buf;\n"
+          "    if (!brpc_message_serialize_delimited(request, request_buf)) {\n"
+          "      cntl->SetFailed(brpc::EREQUEST, \"brpc-rs failed to pass "
+          "request\");\n"
+          "      return;\n"
+          "    }\n"
+          "    butil::ZeroCopyBuf zc_request(request_buf);\n"
+          "    // The Rust handler takes over `done` and runs it once it has\n"
+          "    // filled `response`, possibly after this method returns.\n"
+          "    $method_name$_trampoline($method_name$_closure_ptr, cntl, "
+          "zc_request,\n"
+          "        response, done_guard.release());\n"
           "  }\n"
           "\n"
-          "  int (*$method_name$_trampoline)(\n"
+          "  void (*$method_name$_trampoline)(\n"
           "                void *,\n"
+          "                brpc::Controller *,\n"
           "                butil::ZeroCopyBuf &,\n"
-          "                butil::ZeroCopyBufMut &);\n"
+          "                google::protobuf::Message *,\n"
+          "                google::protobuf::Closure *);\n"
           "  void *$method_name$_closure_ptr;\n\n",
-          "method_name", method->name());
+          "method_name", method->name(), "input",
+          ClassName(method->input_type()), "output",
+          ClassName(method->output_type()));
     }
     cpp_printer.Print("};\n"); // Service class ends
   }
@@ -241,7 +341,7 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
         "  delete service_ptr;\n"
         "}\n"
         "brpc_$service_name$_stub_t brpc_$service_name$Stub_with_channel(\n"
-        "    brpc::Channel *ch"
+        "    google::protobuf::RpcChannel *ch"
         ") {\n"
         "  return new $package_name$::$service_name$_Stub(ch);\n"
         "}\n"
@@ -262,8 +362,10 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
           "void brpc_$service_name$_$method_name$_set_handler(\n"
           "  brpc_$service_name$_service_t service,\n"
           "  void *rust_closure_ptr,\n"
-          "  int (*trampoline)(void *, butil::ZeroCopyBuf &, \n"
-          "                     butil::ZeroCopyBufMut &))\n"
+          "  void (*trampoline)(void *, brpc::Controller *,\n"
+          "                     butil::ZeroCopyBuf &,\n"
+          "                     google::protobuf::Message *,\n"
+          "                     google::protobuf::Closure *))\n"
           "{\n"
           "  $package_name$::$service_name$Impl *service_ptr = \n"
           "    static_cast<$package_name$::$service_name$Impl *>(service);\n"
@@ -273,16 +375,65 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
           "method_name", method->name(), "service_name", service->name(),
           "package_name", file->package());
 
+      std::map<std::string, std::string> vars;
+      vars["method_name"] = method->name();
+      vars["service_name"] = service->name();
+      vars["package_name"] = file->package();
+      vars["input"] = ClassName(method->input_type());
+      vars["output"] = ClassName(method->output_type());
+      vars["method_index"] = std::to_string(j);
       cpp_printer.Print(
+          vars,
           "void brpc_$service_name$Stub_$method_name$(\n"
           "  brpc_$service_name$_stub_t stub,\n"
-          "  brpc::Controller *cntl) {\n"
+          "  brpc::Controller *cntl,\n"
+          "  butil::IOBuf &request_buf,\n"
+          "  butil::IOBuf &response_buf) {\n"
           "  $package_name$::$service_name$_Stub *stub_ptr = \n"
           "    static_cast<$package_name$::$service_name$_Stub *>(stub);\n"
-          "  stub_ptr->$method_name$(cntl, NULL, NULL, NULL);\n"
-          "}\n",
-          "method_name", method->name(), "service_name", service->name(),
-          "package_name", file->package());
+          "  $input$ request;\n"
+          "  $output$ response;\n"
+          "  if (!brpc_message_parse_delimited(&request, request_buf)) {\n"
+          "    cntl->SetFailed(brpc::EREQUEST, \"brpc-rs failed to pass "
+          "request\");\n"
+          "    return;\n"
+          "  }\n"
+          "  brpc_call_method(stub_ptr->channel(),\n"
+          "    $package_name$::$service_name$::descriptor()->method("
+          "$method_index$),\n"
+          "    cntl, &request, &response, NULL);\n"
+          "  if (!cntl->Failed() &&\n"
+          "      !brpc_message_serialize_delimited(&response, response_buf)) {\n"
+          "    cntl->SetFailed(brpc::ERESPONSE, \"brpc-rs failed to pass "
+          "response\");\n"
+          "  }\n"
+          "}\n");
+
+      cpp_printer.Print(
+          vars,
+          "void brpc_$service_name$Stub_$method_name$_async(\n"
+          "  brpc_$service_name$_stub_t stub,\n"
+          "  brpc::Controller *cntl,\n"
+          "  butil::IOBuf &request_buf,\n"
+          "  butil::IOBuf &response_buf,\n"
+          "  void (*callback)(void *),\n"
+          "  void *arg) {\n"
+          "  $package_name$::$service_name$_Stub *stub_ptr = \n"
+          "    static_cast<$package_name$::$service_name$_Stub *>(stub);\n"
+          "  AsyncCallDone<$input$, $output$> *done =\n"
+          "    new AsyncCallDone<$input$, $output$>(\n"
+          "      cntl, &response_buf, callback, arg);\n"
+          "  if (!brpc_message_parse_delimited(&done->request, request_buf)) {\n"
+          "    cntl->SetFailed(brpc::EREQUEST, \"brpc-rs failed to pass "
+          "request\");\n"
+          "    done->Run();\n"
+          "    return;\n"
+          "  }\n"
+          "  brpc_call_method(stub_ptr->channel(),\n"
+          "    $package_name$::$service_name$::descriptor()->method("
+          "$method_index$),\n"
+          "    cntl, &done->request, &done->response, done);\n"
+          "}\n");
     }
   }
 
@@ -294,13 +445,29 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
   google::protobuf::io::Printer rs_printer(rust_file, '$');
 
   rs_printer.Print(
-      "use std::os::raw::{c_int, c_void};\n\n"
-      "use brpc_rs::{BrpcError, BrpcResult, Channel, Controller, Service};\n"
-      "use brpc_rs::internal::ffi::{BrpcChannel, BrpcController};\n"
-      "use brpc_rs::internal::zero_copy::{ZeroCopyBuf, ZeroCopyBufMut};\n"
-      "use brpc_rs::internal::zero_copy::{BrpcZeroCopyBuf, "
-      "BrpcZeroCopyBufMut};\n"
-      "use prost::Message; // Trait\n\n");
+      "use std::any::Any;\n"
+      "use std::os::raw::c_void;\n"
+      "use std::sync::Arc;\n\n"
+      "use brpc_rs::{catch_handler_panic, BrpcError, BrpcResult, ChannelBase, "
+      "Controller, Responder, ResponseFuture, RpcResult, ServerContext, "
+      "Service};\n"
+      "use brpc_rs::internal::ffi::{BrpcClosure, BrpcController, BrpcIOBuf, "
+      "BrpcMessage, BrpcRpcChannel};\n"
+      "use brpc_rs::internal::zero_copy::{IOBuf, ZeroCopyBuf, ZeroCopyBufMut};\n"
+      "use brpc_rs::internal::zero_copy::BrpcZeroCopyBuf;\n"
+      "use prost::Message; // Trait\n\n"
+      "fn encode_to_iobuf<T: Message>(msg: &T, buf: *mut BrpcIOBuf) -> "
+      "BrpcResult<()> {\n"
+      "    let mut zc_buf = unsafe { ZeroCopyBufMut::from_iobuf(buf) };\n"
+      "    msg.encode_length_delimited(&mut zc_buf).map_err(|_| "
+      "BrpcError::ESERIALIZE)\n"
+      "}\n\n"
+      "fn decode_from_iobuf<T: Message + Default>(buf: *mut BrpcIOBuf) -> "
+      "BrpcResult<T> {\n"
+      "    let zc_buf = unsafe { ZeroCopyBuf::from_iobuf(buf) };\n"
+      "    T::decode_length_delimited(zc_buf).map_err(|_| "
+      "BrpcError::EDESERIALIZE)\n"
+      "}\n\n");
 
   for (int i = 0; i < file->service_count(); ++i) {
     const google::protobuf::ServiceDescriptor *service = file->service(i);
@@ -308,15 +475,46 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
     rs_printer.Print(
         "pub enum Brpc$service_name$ {}\n"
         "pub enum Brpc$service_name$Stub {}\n"
-        "pub struct $service_name$ { inner: *mut Brpc$service_name$ }\n"
+        "pub struct $service_name$ {\n"
+        "    inner: *mut Brpc$service_name$,\n",
+        "service_name", service->name());
+    // Handlers installed by value are owned by the service
+    for (int j = 0; j < service->method_count(); ++j) {
+      rs_printer.Print(
+          "    $method_name$_handler: Option<Box<dyn Any + Send + Sync>>,\n",
+          "method_name", service->method(j)->name());
+    }
+    rs_printer.Print(
+        "}\n"
         "pub struct $service_name$Stub { inner: *mut Brpc$service_name$Stub }\n"
         "\n"
+        "/// Implement `$service_name$Handler` to serve all methods of "
+        "`$service_name$`\n"
+        "/// with one type, see `$service_name$::from_handler()`.\n"
+        "pub trait $service_name$Handler: Send + Sync + 'static {\n",
+        "service_name", service->name());
+    for (int j = 0; j < service->method_count(); ++j) {
+      const google::protobuf::MethodDescriptor *method = service->method(j);
+      rs_printer.Print("    fn $method_name$(&self, ctx: &mut ServerContext, "
+                       "request: &$input$) -> RpcResult<$output$>;\n",
+                       "method_name", method->name(), "input",
+                       method->input_type()->name(), "output",
+                       method->output_type()->name());
+    }
+    rs_printer.Print(
+        "}\n"
+        "\n"
         "impl Service for $service_name$ {\n"
         "    fn get_service_ptr(&self) -> *mut c_void {\n"
         "        self.inner as *mut c_void\n"
         "    }\n"
         "}\n"
         "\n"
+        "// Handlers are owned by the service and are `Send + Sync`, and the C++\n"
+        "// service is only changed through `&mut self`.\n"
+        "unsafe impl Send for $service_name$ {}\n"
+        "unsafe impl Sync for $service_name$ {}\n"
+        "\n"
         "impl Default for $service_name$ {\n"
         "    fn default() -> Self {\n"
         "        Self::new()\n"
@@ -333,11 +531,65 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
         "\n"
         "impl $service_name$ {\n"
         "    pub fn new() -> $service_name$ {\n"
-        "        $service_name$ { inner: unsafe { brpc_$service_name$_new() } "
-        "}\n"
-        "    }\n"
-        "\n\n",
+        "        $service_name$ {\n"
+        "            inner: unsafe { brpc_$service_name$_new() },\n",
         "service_name", service->name());
+    for (int j = 0; j < service->method_count(); ++j) {
+      rs_printer.Print("            $method_name$_handler: None,\n",
+                       "method_name", service->method(j)->name());
+    }
+    rs_printer.Print("        }\n"
+                     "    }\n"
+                     "\n");
+
+    rs_printer.Print(
+        "    /// Create a `$service_name$` whose methods are all served by "
+        "`handler`.\n"
+        "    pub fn from_handler<H: $service_name$Handler>(handler: H) -> "
+        "$service_name$ {\n"
+        "        let handler = Arc::new(handler);\n"
+        "        let mut service = $service_name$::new();\n",
+        "service_name", service->name());
+    for (int j = 0; j < service->method_count(); ++j) {
+      const google::protobuf::MethodDescriptor *method = service->method(j);
+
+      std::map<std::string, std::string> vars;
+      vars["service_name"] = service->name();
+      vars["method_name"] = method->name();
+      vars["input"] = method->input_type()->name();
+      vars["output"] = method->output_type()->name();
+
+      rs_printer.Print(
+          vars,
+          "        unsafe extern \"C\" fn $method_name$_trampoline<H: "
+          "$service_name$Handler>(\n"
+          "            data: *mut c_void,\n"
+          "            cntl: *mut BrpcController,\n"
+          "            zc_req: *mut BrpcZeroCopyBuf,\n"
+          "            response: *mut BrpcMessage,\n"
+          "            done: *mut BrpcClosure,\n"
+          "        ) {\n"
+          "            let mut responder = Responder::from_raw(cntl, response, "
+          "done, encode_to_iobuf::<$output$>);\n"
+          "            let result = catch_handler_panic(cntl, || {\n"
+          "                let buf = ZeroCopyBuf::from_raw_ptr(zc_req);\n"
+          "                let request = $input$::decode_length_delimited(buf)"
+          ".map_err(|_| BrpcError::EDESERIALIZE)?;\n"
+          "                let handler = &*(data as *const H);\n"
+          "                handler.$method_name$(responder.context_mut(), &request)\n"
+          "            });\n"
+          "            responder.respond(result);\n"
+          "        }\n"
+          "        let handler_ptr = Arc::as_ptr(&handler) as *mut c_void;\n"
+          "        unsafe { "
+          "brpc_$service_name$_$method_name$_set_handler(service.inner, "
+          "handler_ptr, $method_name$_trampoline::<H>) };\n"
+          "        service.$method_name$_handler = "
+          "Some(Box::new(handler.clone()));\n");
+    }
+    rs_printer.Print("        service\n"
+                     "    }\n"
+                     "\n\n");
 
     for (int j = 0; j < service->method_count(); ++j) {
       const google::protobuf::MethodDescriptor *method = service->method(j);
@@ -345,49 +597,86 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
       const google::protobuf::Descriptor *input = method->input_type();
       const google::protobuf::Descriptor *output = method->output_type();
 
-      rs_printer.Print("    pub fn set_$method_name$_handler<F>("
-                       "&mut self, rust_fn: &mut F)\n",
-                       "method_name", method->name());
+      std::map<std::string, std::string> vars;
+      vars["service_name"] = service->name();
+      vars["method_name"] = method->name();
+      vars["input"] = input->name();
+      vars["output"] = output->name();
+
       rs_printer.Print(
+          vars,
+          "    /// Set a handler which fills the response before returning.\n"
+          "    pub fn set_$method_name$_handler<F>(&mut self, handler: F)\n"
           "    where\n"
-          "        F: FnMut(&$input$, &mut $output$) -> "
-          "BrpcResult<()> + Send + Sync + 'static {\n"
+          "        F: Fn(&mut ServerContext, &$input$, &mut $output$) -> "
+          "RpcResult<()> + Send + Sync + 'static {\n"
           "        unsafe extern \"C\" fn trampoline<F>(\n"
           "            data: *mut c_void,\n"
-          "            zc_req: *mut c_void,  // function parameter 1\n"
-          "            zc_resp: *mut c_void, // function parameter 2\n"
-          "        ) -> c_int\n"
-          "        where F: FnMut(&$input$, &mut $output$) -> "
-          "BrpcResult<()> + Send + Sync + 'static {\n"
-          "            let buf = ZeroCopyBuf::from_raw_ptr(\n"
-          "                zc_req as *mut BrpcZeroCopyBuf);\n"
-          "            let mut buf_mut = ZeroCopyBufMut::from_raw_ptr(\n"
-          "                zc_resp as *mut BrpcZeroCopyBufMut);\n"
-          "            let closure: &mut F = &mut *(data as *mut F);\n"
-          "            let mut response = $output$::default();\n"
-          "            let request = match "
-          "$input$::decode_length_delimited(buf) {\n"
-          "                Ok(r) => r,\n"
-          "                Err(_e) => return -1, \n"
-          "            };\n"
-          "            match (*closure)(&request, &mut response) {\n"
-          "                Ok(r) => r,\n"
-          "                Err(_e) => return -1, \n"
-          "            };\n"
-          "            match response.encode_length_delimited(&mut buf_mut) {\n"
-          "                Ok(r) => r,\n"
-          "                Err(_e) => return -1, \n"
-          "            };\n"
-          "            0\n"
-          "        }\n",
-          "input", input->name(), "output", output->name());
-      rs_printer.Print(
-          "        let rust_fn_ptr = rust_fn as *mut F as *mut c_void;\n"
+          "            cntl: *mut BrpcController,\n"
+          "            zc_req: *mut BrpcZeroCopyBuf,\n"
+          "            response: *mut BrpcMessage,\n"
+          "            done: *mut BrpcClosure,\n"
+          "        )\n"
+          "        where F: Fn(&mut ServerContext, &$input$, &mut $output$) -> "
+          "RpcResult<()> + Send + Sync + 'static {\n"
+          "            let mut responder = Responder::from_raw(cntl, response, "
+          "done, encode_to_iobuf::<$output$>);\n"
+          "            let result = catch_handler_panic(cntl, || {\n"
+          "                let buf = ZeroCopyBuf::from_raw_ptr(zc_req);\n"
+          "                let request = $input$::decode_length_delimited(buf)"
+          ".map_err(|_| BrpcError::EDESERIALIZE)?;\n"
+          "                let mut response = $output$::default();\n"
+          "                (*(data as *const F))(responder.context_mut(), &request, "
+          "&mut response).map(|_| response)\n"
+          "            });\n"
+          "            responder.respond(result);\n"
+          "        }\n"
+          "        let handler = Box::new(handler);\n"
+          "        let handler_ptr = &*handler as *const F as *mut c_void;\n"
           "        unsafe { "
           "brpc_$service_name$_$method_name$_set_handler(self.inner, "
-          "rust_fn_ptr, trampoline::<F>) };\n"
-          "    }\n",
-          "service_name", service->name(), "method_name", method->name());
+          "handler_ptr, trampoline::<F>) };\n"
+          "        self.$method_name$_handler = Some(handler);\n"
+          "    }\n"
+          "\n"
+          "    /// Set an asynchronous handler, which finishes the RPC through "
+          "the\n"
+          "    /// `Responder` whenever the response is ready, possibly from "
+          "another\n"
+          "    /// thread.\n"
+          "    pub fn set_$method_name$_async_handler<F>(&mut self, handler: F)\n"
+          "    where\n"
+          "        F: Fn($input$, Responder<$output$>) + Send + Sync + 'static {\n"
+          "        unsafe extern \"C\" fn trampoline<F>(\n"
+          "            data: *mut c_void,\n"
+          "            cntl: *mut BrpcController,\n"
+          "            zc_req: *mut BrpcZeroCopyBuf,\n"
+          "            response: *mut BrpcMessage,\n"
+          "            done: *mut BrpcClosure,\n"
+          "        )\n"
+          "        where F: Fn($input$, Responder<$output$>) + Send + Sync + "
+          "'static {\n"
+          "            let responder = Responder::from_raw(cntl, response, done, "
+          "encode_to_iobuf::<$output$>);\n"
+          "            // A panic drops the responder, which fails the RPC\n"
+          "            let _ = catch_handler_panic(cntl, move || {\n"
+          "                let buf = ZeroCopyBuf::from_raw_ptr(zc_req);\n"
+          "                match $input$::decode_length_delimited(buf) {\n"
+          "                    Ok(request) => (*(data as *const F))(request, "
+          "responder),\n"
+          "                    Err(_e) => "
+          "responder.respond(Err(BrpcError::EDESERIALIZE.into())),\n"
+          "                }\n"
+          "                Ok(())\n"
+          "            });\n"
+          "        }\n"
+          "        let handler = Box::new(handler);\n"
+          "        let handler_ptr = &*handler as *const F as *mut c_void;\n"
+          "        unsafe { "
+          "brpc_$service_name$_$method_name$_set_handler(self.inner, "
+          "handler_ptr, trampoline::<F>) };\n"
+          "        self.$method_name$_handler = Some(handler);\n"
+          "    }\n");
     }
     rs_printer.Print("}\n\n");
 
@@ -401,10 +690,11 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
         "    }\n"
         "}\n\n"
         "impl $service_name$Stub {\n"
-        "    pub fn with_channel(ch: &Channel) -> $service_name$Stub {\n"
+        "    pub fn with_channel<C: ChannelBase + ?Sized>(ch: &C) -> "
+        "$service_name$Stub {\n"
         "        $service_name$Stub { \n"
         "            inner: unsafe{ "
-        "brpc_$service_name$Stub_with_channel(ch.inner) }\n"
+        "brpc_$service_name$Stub_with_channel(ch.rpc_channel()) }\n"
         "        }\n"
         "    }\n"
         "\n\n",
@@ -415,22 +705,37 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
       const google::protobuf::Descriptor *input = method->input_type();
       const google::protobuf::Descriptor *output = method->output_type();
 
-      rs_printer.Print("    pub fn $method_name$(&self, request: &$input$) -> "
-                       "BrpcResult<$output$> {\n",
+      rs_printer.Print(
+          "    pub fn $method_name$(&self, request: &$input$) -> "
+          "RpcResult<$output$> {\n"
+          "        let mut cntl = Controller::new();\n"
+          "        self.$method_name$_with(&mut cntl, request)\n"
+          "    }\n"
+          "\n",
+          "method_name", method->name(), "input", input->name(), "output",
+          output->name());
+      rs_printer.Print("    /// Call `$method_name$` with the options set in "
+                       "`cntl`, which tells the\n"
+                       "    /// latency, the server and the retries of the "
+                       "call after it returns.\n"
+                       "    pub fn $method_name$_with(&self, cntl: &mut "
+                       "Controller, request: &$input$) -> "
+                       "RpcResult<$output$> {\n",
                        "method_name", method->name(), "input", input->name(),
                        "output", output->name());
       rs_printer.Print(
-          "        let cntl = Controller::new();\n"
+          "        let request_iobuf = IOBuf::new();\n"
+          "        let response_iobuf = IOBuf::new();\n"
           "        let mut request_buf = unsafe { ZeroCopyBufMut::from_iobuf(\n"
-          "            cntl.request_attachment()\n"
+          "            request_iobuf.as_ptr()\n"
           "        ) };\n"
           "        request.encode_length_delimited(&mut "
           "request_buf).map_err(|_| BrpcError::ESERIALIZE)?;\n"
           "        unsafe { brpc_$service_name$Stub_$method_name$(self.inner, "
-          "cntl.inner) };\n"
-          "        if cntl.failed() { return Err(cntl.error()); }\n"
+          "cntl.inner, request_iobuf.as_ptr(), response_iobuf.as_ptr()) };\n"
+          "        if cntl.failed() { return Err(cntl.status()); }\n"
           "        let response_buf = unsafe { ZeroCopyBuf::from_iobuf(\n"
-          "            cntl.response_attachment()\n"
+          "            response_iobuf.as_ptr()\n"
           "        ) };\n"
           "        let response = "
           "$output$::decode_length_delimited(response_buf).map_err(|_| "
@@ -439,12 +744,41 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
           "    }\n",
           "service_name", service->name(), "method_name", method->name(),
           "output", output->name());
-      rs_printer.Print("}\n\n");
+
+      rs_printer.Print(
+          "\n"
+          "    pub fn $method_name$_async(&self, request: &$input$) -> "
+          "ResponseFuture<$output$> {\n"
+          "        let mut future = "
+          "ResponseFuture::new(decode_from_iobuf::<$output$>);\n"
+          "        let mut request_buf = unsafe { ZeroCopyBufMut::from_iobuf(\n"
+          "            future.request_buf()\n"
+          "        ) };\n"
+          "        match request.encode_length_delimited(&mut request_buf) {\n"
+          "            Ok(_) => {\n"
+          "                let (done, arg) = future.done_callback();\n"
+          "                unsafe { "
+          "brpc_$service_name$Stub_$method_name$_async(self.inner, "
+          "future.controller(), future.request_buf(), future.response_buf(), "
+          "done, arg) };\n"
+          "            }\n"
+          "            Err(_) => future.fail(BrpcError::ESERIALIZE),\n"
+          "        }\n"
+          "        future\n"
+          "    }\n",
+          "service_name", service->name(), "method_name", method->name(),
+          "input", input->name(), "output", output->name());
     }
+    rs_printer.Print("}\n\n");
   }
 
-  rs_printer.Print("type Trampoline = unsafe extern \"C\" fn(*mut c_void, *mut "
-                   "c_void, *mut c_void) -> c_int;\n\n");
+  rs_printer.Print("type Trampoline = unsafe extern \"C\" fn(\n"
+                   "    *mut c_void,\n"
+                   "    *mut BrpcController,\n"
+                   "    *mut BrpcZeroCopyBuf,\n"
+                   "    *mut BrpcMessage,\n"
+                   "    *mut BrpcClosure,\n"
+                   ");\n\n");
 
   rs_printer.Print("extern \"C\" {\n");
   for (int i = 0; i < file->service_count(); ++i) {
@@ -453,7 +787,7 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
     rs_printer.Print(
         "fn brpc_$service_name$_new() -> *mut Brpc$service_name$;\n"
         "fn brpc_$service_name$_destroy(service: *mut Brpc$service_name$);\n"
-        "fn brpc_$service_name$Stub_with_channel(ch: *mut BrpcChannel) -> *mut "
+        "fn brpc_$service_name$Stub_with_channel(ch: *mut BrpcRpcChannel) -> *mut "
         "Brpc$service_name$Stub;\n"
         "fn brpc_$service_name$Stub_destroy(service: *mut "
         "Brpc$service_name$Stub);\n",
@@ -469,7 +803,18 @@ bool BrpcToProtobuf::Generate(const google::protobuf::FileDescriptor *file,
                        "\n"
                        "fn brpc_$service_name$Stub_$method_name$(\n"
                        "    stub: *mut Brpc$service_name$Stub,\n"
-                       "    cntl: *mut BrpcController\n"
+                       "    cntl: *mut BrpcController,\n"
+                       "    request_buf: *mut BrpcIOBuf,\n"
+                       "    response_buf: *mut BrpcIOBuf\n"
+                       ");\n"
+                       "\n"
+                       "fn brpc_$service_name$Stub_$method_name$_async(\n"
+                       "    stub: *mut Brpc$service_name$Stub,\n"
+                       "    cntl: *mut BrpcController,\n"
+                       "    request_buf: *mut BrpcIOBuf,\n"
+                       "    response_buf: *mut BrpcIOBuf,\n"
+                       "    done: extern \"C\" fn(*mut c_void),\n"
+                       "    arg: *mut c_void\n"
                        ");\n"
                        "\n",
                        "service_name", service->name(), "method_name",
@@ -12,23 +12,120 @@
 // limitations under the License.
 
 #include <brpc/channel.h>
+#include <brpc/parallel_channel.h>
+#include <brpc/partition_channel.h>
+#include <brpc/retry_policy.h>
+#include <brpc/selective_channel.h>
 #include <brpc/server.h>
+#include <bthread/bthread.h>
+#include <bthread/countdown_event.h>
+#include <bthread/unstable.h>
+#include <butil/logging.h>
+#include <butil/time.h>
+#include <google/protobuf/io/coded_stream.h>
+#include <openssl/bio.h>
+#include <openssl/x509.h>
+#include <mutex>
+#include <string.h>
 
 extern "C" {
 size_t iobuf_size(butil::IOBuf &buf) { return buf.size(); }
-bool brpc_is_asked_to_quit(void) { return brpc::IsAskedToQuit(); }
+butil::IOBuf *brpc_iobuf_new() { return new butil::IOBuf; }
+void brpc_iobuf_destroy(butil::IOBuf *buf) { delete buf; }
+size_t brpc_iobuf_size(const butil::IOBuf &buf) { return buf.size(); }
+size_t brpc_iobuf_copy_to(const butil::IOBuf &buf, void *data, size_t n) {
+  return buf.copy_to(data, n);
 }
+void brpc_iobuf_assign(butil::IOBuf &buf, const void *data, size_t n) {
+  buf.clear();
+  buf.append(data, n);
+}
+int brpc_is_asked_to_quit(void) { return brpc::IsAskedToQuit(); }
+void brpc_log_error(const char *text) { LOG(ERROR) << text; }
+}
+
+// Messages are handed over between C++ and Rust (prost) as length-delimited
+// bytes in an IOBuf.
+extern "C" {
+int brpc_message_serialize_delimited(const google::protobuf::Message *msg,
+                                     butil::IOBuf &buf) {
+  butil::IOBufAsZeroCopyOutputStream stream(&buf);
+  google::protobuf::io::CodedOutputStream coded(&stream);
+  coded.WriteVarint32(msg->ByteSize());
+  msg->SerializeWithCachedSizes(&coded);
+  return !coded.HadError();
+}
+
+int brpc_message_parse_delimited(google::protobuf::Message *msg,
+                                 const butil::IOBuf &buf) {
+  butil::IOBufAsZeroCopyInputStream stream(buf);
+  google::protobuf::io::CodedInputStream coded(&stream);
+  uint32_t size = 0;
+  if (!coded.ReadVarint32(&size)) {
+    return 0;
+  }
+  coded.PushLimit(size);
+  // A truncated body would leave bytes before the limit
+  return msg->ParseFromCodedStream(&coded) && coded.ConsumedEntireMessage() &&
+         coded.BytesUntilLimit() == 0;
+}
+
+void brpc_closure_run(google::protobuf::Closure *done) { done->Run(); }
+}
+
+// A brpc::Server which remembers whether a panicking Rust handler aborts
+// the process.
+class RustServer : public brpc::Server {
+public:
+  bool abort_on_panic = false;
+};
 
 // brpc::Server
 extern "C" {
-brpc::Server *brpc_server_new() { return new brpc::Server; }
+brpc::Server *brpc_server_new() { return new RustServer; }
 
 void brpc_server_destroy(brpc::Server *server) { delete server; }
 
+void brpc_server_set_abort_on_panic(brpc::Server *server, int abort) {
+  static_cast<RustServer *>(server)->abort_on_panic = abort;
+}
+
+// Services are always owned and destroyed by the Rust `Server`.
 int brpc_server_add_service(brpc::Server *server,
-                            ::google::protobuf::Service *service,
-                            brpc::ServiceOwnership ownership) {
-  return server->AddService(service, ownership);
+                            ::google::protobuf::Service *service) {
+  return server->AddService(service, brpc::SERVER_DOESNT_OWN_SERVICE);
+}
+
+// Limit concurrent calls to `method_name` of the service named `service_name`
+// (either the full name or the bare one) to `max_concurrency`, 0 meaning
+// unlimited, or to an automatic limit if `max_concurrency` is negative. Must
+// be called before the server is started.
+int brpc_server_set_method_max_concurrency(brpc::Server *server,
+                                           const char *service_name,
+                                           const char *method_name,
+                                           int max_concurrency) {
+  if (server->IsRunning()) {
+    return brpc::EINTERNAL;
+  }
+  const brpc::Server::ServiceProperty *sp =
+      server->FindServicePropertyByFullName(service_name);
+  if (sp == NULL) {
+    sp = server->FindServicePropertyByName(service_name);
+  }
+  if (sp == NULL || sp->service == NULL) {
+    return brpc::ENOSERVICE;
+  }
+  if (sp->service->GetDescriptor()->FindMethodByName(method_name) == NULL) {
+    return brpc::ENOMETHOD;
+  }
+  brpc::AdaptiveMaxConcurrency &limit =
+      server->MaxConcurrencyOf(sp->service, method_name);
+  if (max_concurrency < 0) {
+    limit = "auto";
+  } else {
+    limit = max_concurrency;
+  }
+  return 0;
 }
 
 int brpc_server_start(brpc::Server *server, int port,
@@ -40,6 +137,20 @@ void brpc_server_run_until_asked_to_quit(brpc::Server *server) {
   return server->RunUntilAskedToQuit();
 }
 
+int brpc_server_stop(brpc::Server *server, int closewait_ms) {
+  return server->Stop(closewait_ms);
+}
+
+int brpc_server_join(brpc::Server *server) { return server->Join(); }
+
+int brpc_server_is_running(brpc::Server *server) {
+  return server->IsRunning();
+}
+
+int brpc_server_listen_port(brpc::Server *server) {
+  return server->listen_address().port;
+}
+
 // brpc::ServerOptions
 brpc::ServerOptions *brpc_server_options_new() {
   return new brpc::ServerOptions;
@@ -53,6 +164,67 @@ void brpc_server_options_set_idle_timeout_ms(brpc::ServerOptions *options,
                                              int timeout) {
   options->idle_timeout_sec = timeout;
 }
+
+void brpc_server_options_set_num_threads(brpc::ServerOptions *options,
+                                         int num_threads) {
+  options->num_threads = num_threads;
+}
+
+void brpc_server_options_set_max_concurrency(brpc::ServerOptions *options,
+                                             int max_concurrency) {
+  options->max_concurrency = max_concurrency;
+}
+
+void brpc_server_options_set_ssl_options(
+    brpc::ServerOptions *options, const brpc::ServerSSLOptions *ssl_options) {
+  *options->mutable_ssl_options() = *ssl_options;
+}
+
+// brpc::ServerSSLOptions
+brpc::ServerSSLOptions *brpc_server_ssl_options_new() {
+  return new brpc::ServerSSLOptions;
+}
+
+void brpc_server_ssl_options_destroy(brpc::ServerSSLOptions *options) {
+  delete options;
+}
+
+// `certificate` and `private_key` are either file paths or PEM strings
+void brpc_server_ssl_options_set_default_cert(brpc::ServerSSLOptions *options,
+                                              const char *certificate,
+                                              const char *private_key) {
+  options->default_cert.certificate = certificate;
+  options->default_cert.private_key = private_key;
+}
+
+void brpc_server_ssl_options_add_sni_cert(brpc::ServerSSLOptions *options,
+                                          const char *certificate,
+                                          const char *private_key,
+                                          const char *const *sni_filters,
+                                          size_t sni_filter_count) {
+  brpc::CertInfo cert;
+  cert.certificate = certificate;
+  cert.private_key = private_key;
+  for (size_t i = 0; i < sni_filter_count; ++i) {
+    cert.sni_filters.push_back(sni_filters[i]);
+  }
+  options->certs.push_back(cert);
+}
+
+void brpc_server_ssl_options_set_ca_file(brpc::ServerSSLOptions *options,
+                                         const char *ca_file_path) {
+  options->verify.ca_file_path = ca_file_path;
+}
+
+void brpc_server_ssl_options_set_verify_depth(brpc::ServerSSLOptions *options,
+                                              int verify_depth) {
+  options->verify.verify_depth = verify_depth;
+}
+
+void brpc_server_ssl_options_set_ciphers(brpc::ServerSSLOptions *options,
+                                         const char *ciphers) {
+  options->ciphers = ciphers;
+}
 } // extern "C" brpc::Server
 
 // brpc::Channel
@@ -66,11 +238,21 @@ int brpc_channel_init(brpc::Channel *channel, const char *server_addr_and_port,
   return channel->Init(server_addr_and_port, options);
 }
 
+int brpc_channel_init_with_naming_service(brpc::Channel *channel,
+                                          const char *naming_service_url,
+                                          const char *load_balancer_name,
+                                          const brpc::ChannelOptions *options) {
+  return channel->Init(naming_service_url, load_balancer_name, options);
+}
+
+google::protobuf::RpcChannel *
+brpc_channel_as_rpc_channel(brpc::Channel *channel) {
+  return channel;
+}
+
 // brpc::ChannelOptions
 brpc::ChannelOptions *brpc_channel_options_new() {
-  brpc::ChannelOptions *ptr = new brpc::ChannelOptions;
-  ptr->protocol = "http";
-  return ptr;
+  return new brpc::ChannelOptions;
 }
 
 void brpc_channel_options_destroy(brpc::ChannelOptions *options) {
@@ -87,22 +269,872 @@ void brpc_channel_options_set_max_retry(brpc::ChannelOptions *options,
   options->max_retry = max_retry;
 }
 
+void brpc_channel_options_set_protocol(brpc::ChannelOptions *options,
+                                       const char *protocol) {
+  options->protocol = protocol;
+}
+
+void brpc_channel_options_set_backup_request_ms(brpc::ChannelOptions *options,
+                                                int backup_request_ms) {
+  options->backup_request_ms = backup_request_ms;
+}
+
+void brpc_channel_options_set_connection_type(brpc::ChannelOptions *options,
+                                              const char *connection_type) {
+  options->connection_type = connection_type;
+}
+
+void brpc_channel_options_set_connect_timeout_ms(brpc::ChannelOptions *options,
+                                                 int timeout) {
+  options->connect_timeout_ms = timeout;
+}
+
+void brpc_channel_options_set_connection_group(brpc::ChannelOptions *options,
+                                               const char *connection_group) {
+  options->connection_group = connection_group;
+}
+
+// `policy` must outlive the channels made with `options`
+void brpc_channel_options_set_retry_policy(brpc::ChannelOptions *options,
+                                           const brpc::RetryPolicy *policy) {
+  options->retry_policy = policy;
+}
+
+void brpc_channel_options_set_ssl_options(
+    brpc::ChannelOptions *options, const brpc::ChannelSSLOptions *ssl_options) {
+  *options->mutable_ssl_options() = *ssl_options;
+}
+
+brpc::ChannelSSLOptions *brpc_channel_ssl_options_new() {
+  return new brpc::ChannelSSLOptions;
+}
+
+void brpc_channel_ssl_options_destroy(brpc::ChannelSSLOptions *options) {
+  delete options;
+}
+
+void brpc_channel_ssl_options_set_ca_file(brpc::ChannelSSLOptions *options,
+                                          const char *ca_file_path) {
+  options->verify.ca_file_path = ca_file_path;
+}
+
+void brpc_channel_ssl_options_set_verify_depth(
+    brpc::ChannelSSLOptions *options, int verify_depth) {
+  options->verify.verify_depth = verify_depth;
+}
+
+// `certificate` and `private_key` are either file paths or PEM strings
+void brpc_channel_ssl_options_set_client_cert(brpc::ChannelSSLOptions *options,
+                                              const char *certificate,
+                                              const char *private_key) {
+  options->client_cert.certificate = certificate;
+  options->client_cert.private_key = private_key;
+}
+
+void brpc_channel_ssl_options_set_sni_name(brpc::ChannelSSLOptions *options,
+                                           const char *sni_name) {
+  options->sni_name = sni_name;
+}
+
+void brpc_channel_ssl_options_set_ciphers(brpc::ChannelSSLOptions *options,
+                                          const char *ciphers) {
+  options->ciphers = ciphers;
+}
+
 } // extern "C" brpc::Channel
 
+// brpc::ParallelChannel
+//
+// Call mappers and response mergers are implemented in Rust. Messages are
+// handed over as protobuf-encoded bytes in an IOBuf, and `arg` is released by
+// `drop` once BRPC no longer refers to the mapper or merger.
+typedef int (*brpc_call_mapper_fn)(void *arg, int channel_index,
+                                   const char *method,
+                                   const butil::IOBuf &request,
+                                   butil::IOBuf &sub_request);
+typedef int (*brpc_response_merger_fn)(void *arg, butil::IOBuf &response,
+                                       const butil::IOBuf &sub_response);
+
+namespace {
+// Must match `SubCall` in channel.rs
+enum { SUB_CALL_FORWARD, SUB_CALL_REQUEST, SUB_CALL_SKIP, SUB_CALL_BAD };
+// Must match `MergeResult` in channel.rs, and `MERGE_APPENDED` there
+enum { MERGE_MERGED, MERGE_FAIL, MERGE_FAIL_ALL, MERGE_APPENDED };
+
+// Messages are copied without checking required fields, which are checked
+// once by the caller of the RPC.
+bool serialize_to_iobuf(const google::protobuf::Message *msg,
+                        butil::IOBuf *buf) {
+  butil::IOBufAsZeroCopyOutputStream stream(buf);
+  return msg->SerializePartialToZeroCopyStream(&stream);
+}
+
+bool merge_from_iobuf(google::protobuf::Message *msg,
+                      const butil::IOBuf &buf) {
+  butil::IOBufAsZeroCopyInputStream stream(buf);
+  google::protobuf::io::CodedInputStream coded(&stream);
+  return msg->MergePartialFromCodedStream(&coded) &&
+         coded.ConsumedEntireMessage();
+}
+
+bool parse_from_iobuf(google::protobuf::Message *msg,
+                      const butil::IOBuf &buf) {
+  msg->Clear();
+  return merge_from_iobuf(msg, buf);
+}
+
+class RustCallMapper : public brpc::CallMapper {
+public:
+  RustCallMapper(brpc_call_mapper_fn map, void *arg, void (*drop)(void *))
+      : _map(map), _arg(arg), _drop(drop) {}
+
+  brpc::SubCall Map(int channel_index,
+                    const google::protobuf::MethodDescriptor *method,
+                    const google::protobuf::Message *request,
+                    google::protobuf::Message *response) {
+    butil::IOBuf request_buf;
+    butil::IOBuf sub_request_buf;
+    if (!serialize_to_iobuf(request, &request_buf)) {
+      return brpc::SubCall::Bad();
+    }
+    switch (_map(_arg, channel_index, method->full_name().c_str(), request_buf,
+                 sub_request_buf)) {
+    case SUB_CALL_FORWARD:
+      return brpc::SubCall(method, request, response->New(),
+                           brpc::DELETE_RESPONSE);
+    case SUB_CALL_REQUEST: {
+      google::protobuf::Message *sub_request = request->New();
+      if (!parse_from_iobuf(sub_request, sub_request_buf)) {
+        delete sub_request;
+        return brpc::SubCall::Bad();
+      }
+      return brpc::SubCall(method, sub_request, response->New(),
+                           brpc::DELETE_REQUEST | brpc::DELETE_RESPONSE);
+    }
+    case SUB_CALL_SKIP:
+      return brpc::SubCall::Skip();
+    default:
+      return brpc::SubCall::Bad();
+    }
+  }
+
+protected:
+  ~RustCallMapper() { _drop(_arg); }
+
+private:
+  brpc_call_mapper_fn _map;
+  void *_arg;
+  void (*_drop)(void *);
+};
+
+class RustResponseMerger : public brpc::ResponseMerger {
+public:
+  RustResponseMerger(brpc_response_merger_fn merge, void *arg,
+                     void (*drop)(void *))
+      : _merge(merge), _arg(arg), _drop(drop) {}
+
+  Result Merge(google::protobuf::Message *response,
+               const google::protobuf::Message *sub_response) {
+    butil::IOBuf response_buf;
+    butil::IOBuf sub_response_buf;
+    if (!serialize_to_iobuf(response, &response_buf) ||
+        !serialize_to_iobuf(sub_response, &sub_response_buf)) {
+      return FAIL;
+    }
+    switch (_merge(_arg, response_buf, sub_response_buf)) {
+    case MERGE_MERGED:
+      return parse_from_iobuf(response, response_buf) ? MERGED : FAIL;
+    case MERGE_APPENDED:
+      // `response_buf` only holds what was appended to the response, and
+      // parsing concatenated messages merges them
+      return merge_from_iobuf(response, response_buf) ? MERGED : FAIL;
+    case MERGE_FAIL_ALL:
+      return FAIL_ALL;
+    default:
+      return FAIL;
+    }
+  }
+
+protected:
+  ~RustResponseMerger() { _drop(_arg); }
+
+private:
+  brpc_response_merger_fn _merge;
+  void *_arg;
+  void (*_drop)(void *);
+};
+} // namespace
+
+extern "C" {
+brpc::ParallelChannel *brpc_parallel_channel_new() {
+  return new brpc::ParallelChannel;
+}
+
+void brpc_parallel_channel_destroy(brpc::ParallelChannel *channel) {
+  delete channel;
+}
+
+int brpc_parallel_channel_init(brpc::ParallelChannel *channel,
+                               const brpc::ParallelChannelOptions *options) {
+  return channel->Init(options);
+}
+
+// A NULL `map` or `merge` selects the default of BRPC: the request is sent
+// unchanged and responses are merged with `MergeFrom()`.
+int brpc_parallel_channel_add_channel(brpc::ParallelChannel *channel,
+                                      brpc::Channel *sub_channel,
+                                      brpc_call_mapper_fn map, void *map_arg,
+                                      void (*drop_map_arg)(void *),
+                                      brpc_response_merger_fn merge,
+                                      void *merge_arg,
+                                      void (*drop_merge_arg)(void *)) {
+  butil::intrusive_ptr<brpc::CallMapper> mapper;
+  if (map != NULL) {
+    mapper.reset(new RustCallMapper(map, map_arg, drop_map_arg));
+  }
+  butil::intrusive_ptr<brpc::ResponseMerger> merger;
+  if (merge != NULL) {
+    merger.reset(new RustResponseMerger(merge, merge_arg, drop_merge_arg));
+  }
+  return channel->AddChannel(sub_channel, brpc::DOESNT_OWN_CHANNEL, mapper,
+                             merger);
+}
+
+int brpc_parallel_channel_channel_count(brpc::ParallelChannel *channel) {
+  return channel->channel_count();
+}
+
+google::protobuf::RpcChannel *
+brpc_parallel_channel_as_rpc_channel(brpc::ParallelChannel *channel) {
+  return channel;
+}
+
+brpc::ParallelChannelOptions *brpc_parallel_channel_options_new() {
+  return new brpc::ParallelChannelOptions;
+}
+
+void brpc_parallel_channel_options_destroy(
+    brpc::ParallelChannelOptions *options) {
+  delete options;
+}
+
+void brpc_parallel_channel_options_set_timeout_ms(
+    brpc::ParallelChannelOptions *options, int timeout) {
+  options->timeout_ms = timeout;
+}
+
+void brpc_parallel_channel_options_set_fail_limit(
+    brpc::ParallelChannelOptions *options, int fail_limit) {
+  options->fail_limit = fail_limit;
+}
+
+void brpc_parallel_channel_options_set_success_limit(
+    brpc::ParallelChannelOptions *options, int success_limit) {
+  options->success_limit = success_limit;
+}
+} // extern "C" brpc::ParallelChannel
+
+// brpc::SelectiveChannel
+extern "C" {
+brpc::SelectiveChannel *brpc_selective_channel_new() {
+  return new brpc::SelectiveChannel;
+}
+
+void brpc_selective_channel_destroy(brpc::SelectiveChannel *channel) {
+  delete channel;
+}
+
+int brpc_selective_channel_init(brpc::SelectiveChannel *channel,
+                                const char *load_balancer_name,
+                                const brpc::ChannelOptions *options) {
+  return channel->Init(load_balancer_name, options);
+}
+
+// On success the SelectiveChannel owns `sub_channel`
+int brpc_selective_channel_add_channel(brpc::SelectiveChannel *channel,
+                                       brpc::Channel *sub_channel,
+                                       uint64_t *handle) {
+  brpc::SelectiveChannel::ChannelHandle channel_handle;
+  int ret = channel->AddChannel(sub_channel, &channel_handle);
+  *handle = channel_handle;
+  return ret;
+}
+
+void brpc_selective_channel_remove_channel(brpc::SelectiveChannel *channel,
+                                           uint64_t handle) {
+  channel->RemoveAndDestroyChannel(handle);
+}
+
+google::protobuf::RpcChannel *
+brpc_selective_channel_as_rpc_channel(brpc::SelectiveChannel *channel) {
+  return channel;
+}
+} // extern "C" brpc::SelectiveChannel
+
+// brpc::PartitionChannel and brpc::DynamicPartitionChannel
+//
+// Partition parsers are implemented in Rust, `arg` is released by `drop` when
+// the channel deletes the parser.
+typedef int (*brpc_partition_parser_fn)(void *arg, const char *tag,
+                                        int *index, int *num_partition_kinds);
+
+namespace {
+class RustPartitionParser : public brpc::PartitionParser {
+public:
+  RustPartitionParser(brpc_partition_parser_fn parse, void *arg,
+                      void (*drop)(void *))
+      : _parse(parse), _arg(arg), _drop(drop) {}
+  ~RustPartitionParser() { _drop(_arg); }
+
+  bool ParseFromTag(const std::string &tag, brpc::Partition *out) {
+    return _parse(_arg, tag.c_str(), &out->index, &out->num_partition_kinds);
+  }
+
+private:
+  brpc_partition_parser_fn _parse;
+  void *_arg;
+  void (*_drop)(void *);
+};
+} // namespace
+
+extern "C" {
+brpc::PartitionChannel *brpc_partition_channel_new() {
+  return new brpc::PartitionChannel;
+}
+
+void brpc_partition_channel_destroy(brpc::PartitionChannel *channel) {
+  delete channel;
+}
+
+int brpc_partition_channel_init(brpc::PartitionChannel *channel,
+                                int num_partition_kinds,
+                                brpc_partition_parser_fn parse, void *parse_arg,
+                                void (*drop_parse_arg)(void *),
+                                const char *naming_service_url,
+                                const char *load_balancer_name,
+                                const brpc::PartitionChannelOptions *options) {
+  return channel->Init(
+      num_partition_kinds,
+      new RustPartitionParser(parse, parse_arg, drop_parse_arg),
+      naming_service_url, load_balancer_name, options);
+}
+
+int brpc_partition_channel_partition_count(brpc::PartitionChannel *channel) {
+  return channel->partition_count();
+}
+
+google::protobuf::RpcChannel *
+brpc_partition_channel_as_rpc_channel(brpc::PartitionChannel *channel) {
+  return channel;
+}
+
+brpc::DynamicPartitionChannel *brpc_dynamic_partition_channel_new() {
+  return new brpc::DynamicPartitionChannel;
+}
+
+void brpc_dynamic_partition_channel_destroy(
+    brpc::DynamicPartitionChannel *channel) {
+  delete channel;
+}
+
+int brpc_dynamic_partition_channel_init(
+    brpc::DynamicPartitionChannel *channel, brpc_partition_parser_fn parse,
+    void *parse_arg, void (*drop_parse_arg)(void *),
+    const char *naming_service_url, const char *load_balancer_name,
+    const brpc::PartitionChannelOptions *options) {
+  return channel->Init(
+      new RustPartitionParser(parse, parse_arg, drop_parse_arg),
+      naming_service_url, load_balancer_name, options);
+}
+
+google::protobuf::RpcChannel *brpc_dynamic_partition_channel_as_rpc_channel(
+    brpc::DynamicPartitionChannel *channel) {
+  return channel;
+}
+
+brpc::PartitionChannelOptions *
+brpc_partition_channel_options_new(const brpc::ChannelOptions *options) {
+  brpc::PartitionChannelOptions *partition_options =
+      new brpc::PartitionChannelOptions;
+  *static_cast<brpc::ChannelOptions *>(partition_options) = *options;
+  return partition_options;
+}
+
+void brpc_partition_channel_options_destroy(
+    brpc::PartitionChannelOptions *options) {
+  delete options;
+}
+
+void brpc_partition_channel_options_set_fail_limit(
+    brpc::PartitionChannelOptions *options, int fail_limit) {
+  options->fail_limit = fail_limit;
+}
+
+void brpc_partition_channel_options_set_succeed_without_server(
+    brpc::PartitionChannelOptions *options, int succeed_without_server) {
+  options->succeed_without_server = succeed_without_server;
+}
+
+void brpc_partition_channel_options_set_call_mapper(
+    brpc::PartitionChannelOptions *options, brpc_call_mapper_fn map,
+    void *map_arg, void (*drop_map_arg)(void *)) {
+  options->call_mapper.reset(new RustCallMapper(map, map_arg, drop_map_arg));
+}
+
+void brpc_partition_channel_options_set_response_merger(
+    brpc::PartitionChannelOptions *options, brpc_response_merger_fn merge,
+    void *merge_arg, void (*drop_merge_arg)(void *)) {
+  options->response_merger.reset(
+      new RustResponseMerger(merge, merge_arg, drop_merge_arg));
+}
+} // extern "C" brpc::PartitionChannel
+
+// Channels implemented in Rust, e.g. test doubles. `call` serves a call by
+// filling `response` or failing `cntl`, and `arg` is released by `drop` when
+// the channel is destroyed.
+typedef void (*brpc_rust_channel_call_fn)(void *arg, const char *method,
+                                          brpc::Controller *cntl,
+                                          const butil::IOBuf &request,
+                                          butil::IOBuf &response);
+
+namespace {
+class RustChannel : public google::protobuf::RpcChannel {
+public:
+  RustChannel(brpc_rust_channel_call_fn call, void *arg, void (*drop)(void *))
+      : _call(call), _arg(arg), _drop(drop) {}
+  ~RustChannel() { _drop(_arg); }
+
+  void CallMethod(const google::protobuf::MethodDescriptor *method,
+                  google::protobuf::RpcController *cntl_base,
+                  const google::protobuf::Message *request,
+                  google::protobuf::Message *response,
+                  google::protobuf::Closure *done) {
+    brpc::ClosureGuard done_guard(done);
+    brpc::Controller *cntl = static_cast<brpc::Controller *>(cntl_base);
+    butil::IOBuf request_buf;
+    butil::IOBuf response_buf;
+    if (!serialize_to_iobuf(request, &request_buf)) {
+      cntl->SetFailed(brpc::EREQUEST, "brpc-rs failed to pass request");
+      return;
+    }
+    _call(_arg, method->full_name().c_str(), cntl, request_buf, response_buf);
+    if (!cntl->Failed() && !parse_from_iobuf(response, response_buf)) {
+      cntl->SetFailed(brpc::ERESPONSE, "brpc-rs failed to pass response");
+    }
+  }
+
+private:
+  brpc_rust_channel_call_fn _call;
+  void *_arg;
+  void (*_drop)(void *);
+};
+} // namespace
+
+extern "C" {
+google::protobuf::RpcChannel *
+brpc_rust_channel_new(brpc_rust_channel_call_fn call, void *arg,
+                      void (*drop)(void *)) {
+  return new RustChannel(call, arg, drop);
+}
+
+void brpc_rust_channel_destroy(google::protobuf::RpcChannel *channel) {
+  delete channel;
+}
+} // extern "C" RustChannel
+
+// Backed off retries
+//
+// BRPC 0.9.6 sends a retry as soon as the retry policy allows it. To back off,
+// a `RustRetryPolicy` ends the failed RPC instead, recording the backoff in its
+// `RustController`, and `brpc_call_method()` issues the call again once the
+// backoff has elapsed. The sub-calls of a `ParallelChannel`, which have their
+// own controllers, are retried right away.
+namespace {
+class RustController : public brpc::Controller {
+public:
+  RustController()
+      : backoff_us(0), retry_policy(NULL), start_us(0),
+        previous_retried_count(0), previous_latency_us(0), canceled(false) {}
+
+  // Set by the retry policy when the failed RPC is to be issued again
+  int64_t backoff_us;
+  // Policy set for the call, which `Reset()` clears
+  const brpc::RetryPolicy *retry_policy;
+  // Start of the first attempt of the call
+  int64_t start_us;
+  // Retries and latency of the attempts before the current one
+  int previous_retried_count;
+  int64_t previous_latency_us;
+  // Guards `canceled` and the reset of the controller between attempts
+  std::mutex mutex;
+  bool canceled;
+};
+
+class SignalEvent : public google::protobuf::Closure {
+public:
+  explicit SignalEvent(bthread::CountdownEvent *event) : _event(event) {}
+  void Run() { _event->signal(); }
+
+private:
+  bthread::CountdownEvent *_event;
+};
+
+// The `done` of every attempt of a call, which issues the call again once
+// the backoff recorded by the retry policy has elapsed, and runs the `done` of
+// the caller after the last attempt.
+class BackoffCall : public google::protobuf::Closure {
+public:
+  BackoffCall(google::protobuf::RpcChannel *channel,
+              const google::protobuf::MethodDescriptor *method,
+              RustController *cntl, const google::protobuf::Message *request,
+              google::protobuf::Message *response,
+              google::protobuf::Closure *done)
+      : _channel(channel), _method(method), _cntl(cntl), _request(request),
+        _response(response), _done(done) {}
+
+  void Issue() {
+    _channel->CallMethod(_method, _cntl, _request, _response, this);
+  }
+
+  void Run() {
+    int64_t backoff_us = _cntl->backoff_us;
+    _cntl->backoff_us = 0;
+    if (backoff_us > 0 && _cntl->Failed()) {
+      bthread_timer_t timer;
+      if (bthread_timer_add(&timer, butil::microseconds_from_now(backoff_us),
+                            OnTimer, this) == 0) {
+        return;
+      }
+      // The call fails with the error of the last attempt
+    }
+    google::protobuf::Closure *done = _done;
+    delete this;
+    done->Run();
+  }
+
+private:
+  // Runs in the timer thread, which must not block
+  static void OnTimer(void *arg) {
+    bthread_t tid;
+    if (bthread_start_background(&tid, NULL, RunReissue, arg) != 0) {
+      RunReissue(arg);
+    }
+  }
+
+  static void *RunReissue(void *arg) {
+    static_cast<BackoffCall *>(arg)->Reissue();
+    return NULL;
+  }
+
+  void Reissue() {
+    bool issue = false;
+    {
+      std::lock_guard<std::mutex> guard(_cntl->mutex);
+      if (_cntl->canceled) {
+        _cntl->SetFailed(ECANCELED, "RPC canceled during the retry backoff");
+      } else {
+        issue = ResetController();
+      }
+    }
+    if (issue) {
+      Issue();
+    } else {
+      Run();
+    }
+  }
+
+  // Reset the controller for the next attempt, keeping the options of the
+  // call. Fails the call if it timed out during the backoff.
+  bool ResetController() {
+    const int64_t now_us = butil::gettimeofday_us();
+    int64_t timeout_ms = _cntl->timeout_ms();
+    if (_cntl->deadline_us() >= 0) {
+      timeout_ms = (_cntl->deadline_us() - now_us) / 1000;
+      if (timeout_ms <= 0) {
+        _cntl->SetFailed(brpc::ERPCTIMEDOUT,
+                         "Reached timeout during the retry backoff");
+        return false;
+      }
+    }
+    // Issuing the call again uses up a retry
+    const int max_retry = _cntl->max_retry() - _cntl->retried_count() - 1;
+    const int retried_count =
+        _cntl->previous_retried_count + _cntl->retried_count() + 1;
+    const uint64_t log_id = _cntl->log_id();
+    const bool has_request_code = _cntl->has_request_code();
+    const uint64_t request_code = _cntl->request_code();
+    const int64_t backup_request_ms = _cntl->backup_request_ms();
+    const brpc::CompressType compress_type = _cntl->request_compress_type();
+    butil::IOBuf attachment;
+    attachment.swap(_cntl->request_attachment());
+
+    _cntl->Reset();
+    _cntl->previous_retried_count = retried_count;
+    _cntl->previous_latency_us = now_us - _cntl->start_us;
+    _cntl->set_timeout_ms(timeout_ms);
+    _cntl->set_max_retry(max_retry);
+    _cntl->set_retry_policy(_cntl->retry_policy);
+    _cntl->set_log_id(log_id);
+    if (has_request_code) {
+      _cntl->set_request_code(request_code);
+    }
+    _cntl->set_backup_request_ms(backup_request_ms);
+    _cntl->set_request_compress_type(compress_type);
+    _cntl->request_attachment().swap(attachment);
+    _response->Clear();
+    return true;
+  }
+
+  google::protobuf::RpcChannel *_channel;
+  const google::protobuf::MethodDescriptor *_method;
+  RustController *_cntl;
+  const google::protobuf::Message *_request;
+  google::protobuf::Message *_response;
+  google::protobuf::Closure *_done;
+};
+} // namespace
+
+extern "C" {
+// Call `method` over `channel` like `RpcChannel::CallMethod()`, backing off
+// the retries of `cntl`. Client stubs call methods with it.
+void brpc_call_method(google::protobuf::RpcChannel *channel,
+                      const google::protobuf::MethodDescriptor *method,
+                      brpc::Controller *cntl,
+                      const google::protobuf::Message *request,
+                      google::protobuf::Message *response,
+                      google::protobuf::Closure *done) {
+  RustController *rust_cntl = dynamic_cast<RustController *>(cntl);
+  if (rust_cntl == NULL) {
+    channel->CallMethod(method, cntl, request, response, done);
+    return;
+  }
+  rust_cntl->backoff_us = 0;
+  rust_cntl->start_us = butil::gettimeofday_us();
+  rust_cntl->previous_retried_count = 0;
+  rust_cntl->previous_latency_us = 0;
+  rust_cntl->canceled = false;
+  if (done != NULL) {
+    (new BackoffCall(channel, method, rust_cntl, request, response, done))
+        ->Issue();
+    return;
+  }
+  // A synchronous call returns after its last attempt
+  bthread::CountdownEvent event;
+  SignalEvent signal(&event);
+  (new BackoffCall(channel, method, rust_cntl, request, response, &signal))
+      ->Issue();
+  event.wait();
+}
+} // extern "C" BackoffCall
+
+// brpc::RetryPolicy
+//
+// Retry policies are implemented in Rust. `do_retry` tells whether to retry
+// the failed RPC of `cntl` and how long to wait before, which `DoRetry()`
+// leaves to `brpc_call_method()`. `arg` is released by `drop` when the policy
+// is destroyed.
+typedef int (*brpc_retry_policy_fn)(void *arg, brpc::Controller *cntl,
+                                    int64_t *backoff_us);
+
+namespace {
+class RustRetryPolicy : public brpc::RetryPolicy {
+public:
+  RustRetryPolicy(brpc_retry_policy_fn do_retry, void *arg,
+                  void (*drop)(void *))
+      : _do_retry(do_retry), _arg(arg), _drop(drop) {}
+
+  ~RustRetryPolicy() { _drop(_arg); }
+
+  bool DoRetry(const brpc::Controller *cntl) const override {
+    brpc::Controller *mut_cntl = const_cast<brpc::Controller *>(cntl);
+    int64_t backoff_us = 0;
+    const bool retry = _do_retry(_arg, mut_cntl, &backoff_us);
+    RustController *rust_cntl = dynamic_cast<RustController *>(mut_cntl);
+    if (retry && backoff_us > 0 && rust_cntl != NULL) {
+      // Called with the call id locked, so the retry is not waited for here
+      rust_cntl->backoff_us = backoff_us;
+      return false;
+    }
+    return retry;
+  }
+
+private:
+  brpc_retry_policy_fn _do_retry;
+  void *_arg;
+  void (*_drop)(void *);
+};
+} // namespace
+
+extern "C" {
+brpc::RetryPolicy *brpc_retry_policy_new(brpc_retry_policy_fn do_retry,
+                                         void *arg, void (*drop)(void *)) {
+  return new RustRetryPolicy(do_retry, arg, drop);
+}
+
+void brpc_retry_policy_destroy(brpc::RetryPolicy *policy) { delete policy; }
+} // extern "C" RustRetryPolicy
+
 // brpc::Controller
 extern "C" {
-brpc::Controller *brpc_controller_new() { return new brpc::Controller; }
+brpc::Controller *brpc_controller_new() { return new RustController; }
 
 void brpc_controller_destroy(brpc::Controller *cntl) { delete cntl; }
 
-bool brpc_controller_failed(brpc::Controller *cntl) { return cntl->Failed(); }
+int brpc_controller_failed(brpc::Controller *cntl) { return cntl->Failed(); }
 
 int brpc_controller_error_code(brpc::Controller *cntl) {
   return cntl->ErrorCode();
 }
 
-void brpc_controller_set_failed(brpc::Controller *cntl, int code) {
-  cntl->SetFailed(code, "brpc-rs controller failed");
+// Cancels a call waiting for a retry backoff too
+void brpc_controller_start_cancel(brpc::Controller *cntl) {
+  RustController *rust_cntl = dynamic_cast<RustController *>(cntl);
+  brpc::CallId call_id;
+  if (rust_cntl != NULL) {
+    std::lock_guard<std::mutex> guard(rust_cntl->mutex);
+    rust_cntl->canceled = true;
+    call_id = cntl->call_id();
+  } else {
+    call_id = cntl->call_id();
+  }
+  brpc::StartCancel(call_id);
+}
+
+void brpc_controller_set_failed(brpc::Controller *cntl, int code,
+                                const char *text) {
+  cntl->SetFailed(code, "%s", text);
+}
+
+// Counts the retries of all attempts of a backed off call
+int brpc_controller_retried_count(brpc::Controller *cntl) {
+  RustController *rust_cntl = dynamic_cast<RustController *>(cntl);
+  int previous = rust_cntl != NULL ? rust_cntl->previous_retried_count : 0;
+  return previous + cntl->retried_count();
+}
+
+// The returned string must be freed by the caller
+char *brpc_controller_error_text(brpc::Controller *cntl) {
+  return strdup(cntl->ErrorText().c_str());
+}
+
+uint64_t brpc_controller_log_id(brpc::Controller *cntl) {
+  return cntl->log_id();
+}
+
+void brpc_controller_set_timeout_ms(brpc::Controller *cntl,
+                                    int64_t timeout_ms) {
+  cntl->set_timeout_ms(timeout_ms);
+}
+
+void brpc_controller_set_max_retry(brpc::Controller *cntl, int max_retry) {
+  cntl->set_max_retry(max_retry);
+}
+
+// `policy` must outlive the RPC
+void brpc_controller_set_retry_policy(brpc::Controller *cntl,
+                                      brpc::RetryPolicy *policy) {
+  cntl->set_retry_policy(policy);
+  RustController *rust_cntl = dynamic_cast<RustController *>(cntl);
+  if (rust_cntl != NULL) {
+    rust_cntl->retry_policy = policy;
+  }
+}
+
+void brpc_controller_set_log_id(brpc::Controller *cntl, uint64_t log_id) {
+  cntl->set_log_id(log_id);
+}
+
+void brpc_controller_set_request_code(brpc::Controller *cntl,
+                                      uint64_t request_code) {
+  cntl->set_request_code(request_code);
+}
+
+void brpc_controller_set_backup_request_ms(brpc::Controller *cntl,
+                                           int64_t timeout_ms) {
+  cntl->set_backup_request_ms(timeout_ms);
+}
+
+void brpc_controller_set_request_compress_type(brpc::Controller *cntl,
+                                               int compress_type) {
+  cntl->set_request_compress_type(
+      static_cast<brpc::CompressType>(compress_type));
+}
+
+// Includes the attempts and backoffs before the last attempt of a call
+int64_t brpc_controller_latency_us(brpc::Controller *cntl) {
+  RustController *rust_cntl = dynamic_cast<RustController *>(cntl);
+  int64_t previous = rust_cntl != NULL ? rust_cntl->previous_latency_us : 0;
+  return previous + cntl->latency_us();
+}
+
+int brpc_controller_has_backup_request(brpc::Controller *cntl) {
+  return cntl->has_backup_request();
+}
+
+// IPv4 address in host byte order
+void brpc_controller_remote_side(brpc::Controller *cntl, uint32_t *ip,
+                                 int *port) {
+  butil::EndPoint remote_side = cntl->remote_side();
+  *ip = ntohl(butil::ip2int(remote_side.ip));
+  *port = remote_side.port;
+}
+
+void brpc_controller_local_side(brpc::Controller *cntl, uint32_t *ip,
+                                int *port) {
+  butil::EndPoint local_side = cntl->local_side();
+  *ip = ntohl(butil::ip2int(local_side.ip));
+  *port = local_side.port;
+}
+
+// Value of the HTTP header `name`, NULL if it is not set. The value lives as
+// long as the RPC.
+const char *brpc_controller_http_header(brpc::Controller *cntl,
+                                        const char *name, size_t *len) {
+  const std::string *value = cntl->http_request().GetHeader(name);
+  if (value == NULL) {
+    return NULL;
+  }
+  *len = value->size();
+  return value->data();
+}
+
+// Subject of the certificate presented by the peer such as "CN=client", NULL
+// if there is none. The returned string must be freed by the caller.
+char *brpc_controller_peer_certificate_subject(brpc::Controller *cntl) {
+  X509 *cert = cntl->get_peer_certificate();
+  if (cert == NULL) {
+    return NULL;
+  }
+  char *subject = NULL;
+  BIO *bio = BIO_new(BIO_s_mem());
+  if (bio != NULL &&
+      X509_NAME_print_ex(bio, X509_get_subject_name(cert), 0,
+                         XN_FLAG_RFC2253) >= 0) {
+    char *data = NULL;
+    long len = BIO_get_mem_data(bio, &data);
+    subject = strndup(data, len);
+  }
+  BIO_free(bio);
+  X509_free(cert);
+  return subject;
+}
+
+// Microseconds left before the deadline set by the client, -1 if there is
+// no deadline.
+int64_t brpc_controller_remaining_deadline_us(brpc::Controller *cntl) {
+  int64_t deadline_us = cntl->deadline_us();
+  if (deadline_us < 0) {
+    return -1;
+  }
+  int64_t remaining_us = deadline_us - butil::gettimeofday_us();
+  return remaining_us > 0 ? remaining_us : 0;
+}
+
+// Whether a panic while serving the RPC of `cntl` aborts the process, which
+// is never the case for RPCs not served by a Rust `Server`.
+int brpc_controller_abort_on_panic(brpc::Controller *cntl) {
+  const RustServer *server = dynamic_cast<const RustServer *>(cntl->server());
+  return server != NULL && server->abort_on_panic;
 }
 
 butil::IOBuf &brpc_controller_get_request_attachment(brpc::Controller *cntl) {
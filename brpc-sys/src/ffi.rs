@@ -15,22 +15,83 @@ use std::os::raw::{c_char, c_int, c_void};
 
 pub enum BrpcServer {} // brpc::Server
 pub enum BrpcServerOptions {} // brpc::ServerOptions
+pub enum BrpcServerSslOptions {} // brpc::ServerSSLOptions
 
 pub enum BrpcChannel {} // brpc::Channel
 pub enum BrpcChannelOptions {} // brpc::ChannelOptions
+pub enum BrpcChannelSslOptions {} // brpc::ChannelSSLOptions
+pub enum BrpcRpcChannel {} // google::protobuf::RpcChannel
+
+pub enum BrpcParallelChannel {} // brpc::ParallelChannel
+pub enum BrpcParallelChannelOptions {} // brpc::ParallelChannelOptions
+pub enum BrpcSelectiveChannel {} // brpc::SelectiveChannel
+pub enum BrpcPartitionChannel {} // brpc::PartitionChannel
+pub enum BrpcDynamicPartitionChannel {} // brpc::DynamicPartitionChannel
+pub enum BrpcPartitionChannelOptions {} // brpc::PartitionChannelOptions
 
 pub enum BrpcController {} // brpc::Controller
+pub enum BrpcRetryPolicy {} // brpc::RetryPolicy
 pub enum BrpcIOBuf {} // butil::IOBuf
+pub enum BrpcMessage {} // google::protobuf::Message
+pub enum BrpcClosure {} // google::protobuf::Closure
+
+// Call mapper and response merger of a sub-channel of a ParallelChannel
+pub type CallMapperFn = extern "C" fn(
+    arg: *mut c_void,
+    channel_index: c_int,
+    method: *const c_char,
+    request: *const BrpcIOBuf,
+    sub_request: *mut BrpcIOBuf,
+) -> c_int;
+pub type ResponseMergerFn = extern "C" fn(
+    arg: *mut c_void,
+    response: *mut BrpcIOBuf,
+    sub_response: *const BrpcIOBuf,
+) -> c_int;
+
+// Serves the calls over a channel implemented in Rust
+pub type RustChannelCallFn = extern "C" fn(
+    arg: *mut c_void,
+    method: *const c_char,
+    cntl: *mut BrpcController,
+    request: *const BrpcIOBuf,
+    response: *mut BrpcIOBuf,
+);
+
+// Partition parser of a PartitionChannel or a DynamicPartitionChannel
+pub type PartitionParserFn = extern "C" fn(
+    arg: *mut c_void,
+    tag: *const c_char,
+    index: *mut c_int,
+    num_partition_kinds: *mut c_int,
+) -> c_int;
+
+// Retry policy of a channel or a call, which also tells how long to wait
+// before the retry
+pub type RetryPolicyFn =
+    extern "C" fn(arg: *mut c_void, cntl: *mut BrpcController, backoff_us: *mut i64) -> c_int;
 
 #[allow(dead_code)]
 extern "C" {
+    pub fn brpc_iobuf_new() -> *mut BrpcIOBuf;
+    pub fn brpc_iobuf_destroy(buf: *mut BrpcIOBuf);
+    pub fn brpc_iobuf_size(buf: *const BrpcIOBuf) -> usize;
+    pub fn brpc_iobuf_copy_to(buf: *const BrpcIOBuf, data: *mut c_void, n: usize) -> usize;
+    pub fn brpc_iobuf_assign(buf: *mut BrpcIOBuf, data: *const c_void, n: usize);
     pub fn brpc_is_asked_to_quit() -> c_int;
+    pub fn brpc_log_error(text: *const c_char);
+    pub fn brpc_message_serialize_delimited(msg: *const BrpcMessage, buf: *mut BrpcIOBuf) -> c_int;
+    pub fn brpc_message_parse_delimited(msg: *mut BrpcMessage, buf: *const BrpcIOBuf) -> c_int;
+    pub fn brpc_closure_run(done: *mut BrpcClosure);
     pub fn brpc_server_new() -> *mut BrpcServer;
     pub fn brpc_server_destroy(server: *mut BrpcServer);
-    pub fn brpc_server_add_service(
+    pub fn brpc_server_set_abort_on_panic(server: *mut BrpcServer, abort: c_int);
+    pub fn brpc_server_add_service(server: *mut BrpcServer, service: *mut c_void) -> c_int;
+    pub fn brpc_server_set_method_max_concurrency(
         server: *mut BrpcServer,
-        service: *mut c_void,
-        ownership: c_int,
+        service_name: *const c_char,
+        method_name: *const c_char,
+        max_concurrency: c_int,
     ) -> c_int;
     pub fn brpc_server_start(
         server: *mut BrpcServer,
@@ -38,6 +99,10 @@ extern "C" {
         opt: *const BrpcServerOptions,
     ) -> c_int;
     pub fn brpc_server_run_until_asked_to_quit(server: *mut BrpcServer);
+    pub fn brpc_server_stop(server: *mut BrpcServer, closewait_ms: c_int) -> c_int;
+    pub fn brpc_server_join(server: *mut BrpcServer) -> c_int;
+    pub fn brpc_server_is_running(server: *mut BrpcServer) -> c_int;
+    pub fn brpc_server_listen_port(server: *mut BrpcServer) -> c_int;
 
     pub fn brpc_server_options_new() -> *mut BrpcServerOptions;
     pub fn brpc_server_options_destroy(server_options: *mut BrpcServerOptions);
@@ -45,6 +110,44 @@ extern "C" {
         server_options: *mut BrpcServerOptions,
         timeout: c_int,
     );
+    pub fn brpc_server_options_set_num_threads(
+        server_options: *mut BrpcServerOptions,
+        num_threads: c_int,
+    );
+    pub fn brpc_server_options_set_max_concurrency(
+        server_options: *mut BrpcServerOptions,
+        max_concurrency: c_int,
+    );
+    pub fn brpc_server_options_set_ssl_options(
+        server_options: *mut BrpcServerOptions,
+        ssl_options: *const BrpcServerSslOptions,
+    );
+    pub fn brpc_server_ssl_options_new() -> *mut BrpcServerSslOptions;
+    pub fn brpc_server_ssl_options_destroy(ssl_options: *mut BrpcServerSslOptions);
+    pub fn brpc_server_ssl_options_set_default_cert(
+        ssl_options: *mut BrpcServerSslOptions,
+        certificate: *const c_char,
+        private_key: *const c_char,
+    );
+    pub fn brpc_server_ssl_options_add_sni_cert(
+        ssl_options: *mut BrpcServerSslOptions,
+        certificate: *const c_char,
+        private_key: *const c_char,
+        sni_filters: *const *const c_char,
+        sni_filter_count: usize,
+    );
+    pub fn brpc_server_ssl_options_set_ca_file(
+        ssl_options: *mut BrpcServerSslOptions,
+        ca_file_path: *const c_char,
+    );
+    pub fn brpc_server_ssl_options_set_verify_depth(
+        ssl_options: *mut BrpcServerSslOptions,
+        verify_depth: c_int,
+    );
+    pub fn brpc_server_ssl_options_set_ciphers(
+        ssl_options: *mut BrpcServerSslOptions,
+        ciphers: *const c_char,
+    );
 
     pub fn brpc_channel_new() -> *mut BrpcChannel;
     pub fn brpc_channel_destroy(channel: *mut BrpcChannel);
@@ -53,6 +156,14 @@ extern "C" {
         server_addr_and_port: *const c_char,
         options: *const BrpcChannelOptions,
     ) -> c_int;
+    pub fn brpc_channel_init_with_naming_service(
+        channel: *mut BrpcChannel,
+        naming_service_url: *const c_char,
+        load_balancer_name: *const c_char,
+        options: *const BrpcChannelOptions,
+    ) -> c_int;
+
+    pub fn brpc_channel_as_rpc_channel(channel: *mut BrpcChannel) -> *mut BrpcRpcChannel;
 
     pub fn brpc_channel_options_new() -> *mut BrpcChannelOptions;
     pub fn brpc_channel_options_destroy(channel_options: *mut BrpcChannelOptions);
@@ -65,11 +176,216 @@ extern "C" {
         channel_options: *mut BrpcChannelOptions,
         max_retry: c_int,
     );
+    pub fn brpc_channel_options_set_protocol(
+        channel_options: *mut BrpcChannelOptions,
+        protocol: *const c_char,
+    );
+    pub fn brpc_channel_options_set_backup_request_ms(
+        channel_options: *mut BrpcChannelOptions,
+        backup_request_ms: c_int,
+    );
+    pub fn brpc_channel_options_set_connection_type(
+        channel_options: *mut BrpcChannelOptions,
+        connection_type: *const c_char,
+    );
+    pub fn brpc_channel_options_set_connect_timeout_ms(
+        channel_options: *mut BrpcChannelOptions,
+        timeout: c_int,
+    );
+    pub fn brpc_channel_options_set_connection_group(
+        channel_options: *mut BrpcChannelOptions,
+        connection_group: *const c_char,
+    );
+    pub fn brpc_channel_options_set_retry_policy(
+        channel_options: *mut BrpcChannelOptions,
+        policy: *const BrpcRetryPolicy,
+    );
+    pub fn brpc_channel_options_set_ssl_options(
+        channel_options: *mut BrpcChannelOptions,
+        ssl_options: *const BrpcChannelSslOptions,
+    );
+    pub fn brpc_channel_ssl_options_new() -> *mut BrpcChannelSslOptions;
+    pub fn brpc_channel_ssl_options_destroy(ssl_options: *mut BrpcChannelSslOptions);
+    pub fn brpc_channel_ssl_options_set_ca_file(
+        ssl_options: *mut BrpcChannelSslOptions,
+        ca_file_path: *const c_char,
+    );
+    pub fn brpc_channel_ssl_options_set_verify_depth(
+        ssl_options: *mut BrpcChannelSslOptions,
+        verify_depth: c_int,
+    );
+    pub fn brpc_channel_ssl_options_set_client_cert(
+        ssl_options: *mut BrpcChannelSslOptions,
+        certificate: *const c_char,
+        private_key: *const c_char,
+    );
+    pub fn brpc_channel_ssl_options_set_sni_name(
+        ssl_options: *mut BrpcChannelSslOptions,
+        sni_name: *const c_char,
+    );
+    pub fn brpc_channel_ssl_options_set_ciphers(
+        ssl_options: *mut BrpcChannelSslOptions,
+        ciphers: *const c_char,
+    );
+
+    pub fn brpc_parallel_channel_new() -> *mut BrpcParallelChannel;
+    pub fn brpc_parallel_channel_destroy(channel: *mut BrpcParallelChannel);
+    pub fn brpc_parallel_channel_init(
+        channel: *mut BrpcParallelChannel,
+        options: *const BrpcParallelChannelOptions,
+    ) -> c_int;
+    pub fn brpc_parallel_channel_add_channel(
+        channel: *mut BrpcParallelChannel,
+        sub_channel: *mut BrpcChannel,
+        map: Option<CallMapperFn>,
+        map_arg: *mut c_void,
+        drop_map_arg: extern "C" fn(*mut c_void),
+        merge: Option<ResponseMergerFn>,
+        merge_arg: *mut c_void,
+        drop_merge_arg: extern "C" fn(*mut c_void),
+    ) -> c_int;
+    pub fn brpc_parallel_channel_channel_count(channel: *mut BrpcParallelChannel) -> c_int;
+    pub fn brpc_parallel_channel_as_rpc_channel(
+        channel: *mut BrpcParallelChannel,
+    ) -> *mut BrpcRpcChannel;
+
+    pub fn brpc_parallel_channel_options_new() -> *mut BrpcParallelChannelOptions;
+    pub fn brpc_parallel_channel_options_destroy(options: *mut BrpcParallelChannelOptions);
+    pub fn brpc_parallel_channel_options_set_timeout_ms(
+        options: *mut BrpcParallelChannelOptions,
+        timeout: c_int,
+    );
+    pub fn brpc_parallel_channel_options_set_fail_limit(
+        options: *mut BrpcParallelChannelOptions,
+        fail_limit: c_int,
+    );
+    pub fn brpc_parallel_channel_options_set_success_limit(
+        options: *mut BrpcParallelChannelOptions,
+        success_limit: c_int,
+    );
+
+    pub fn brpc_selective_channel_new() -> *mut BrpcSelectiveChannel;
+    pub fn brpc_selective_channel_destroy(channel: *mut BrpcSelectiveChannel);
+    pub fn brpc_selective_channel_init(
+        channel: *mut BrpcSelectiveChannel,
+        load_balancer_name: *const c_char,
+        options: *const BrpcChannelOptions,
+    ) -> c_int;
+    pub fn brpc_selective_channel_add_channel(
+        channel: *mut BrpcSelectiveChannel,
+        sub_channel: *mut BrpcChannel,
+        handle: *mut u64,
+    ) -> c_int;
+    pub fn brpc_selective_channel_remove_channel(channel: *mut BrpcSelectiveChannel, handle: u64);
+    pub fn brpc_selective_channel_as_rpc_channel(
+        channel: *mut BrpcSelectiveChannel,
+    ) -> *mut BrpcRpcChannel;
+
+    pub fn brpc_partition_channel_new() -> *mut BrpcPartitionChannel;
+    pub fn brpc_partition_channel_destroy(channel: *mut BrpcPartitionChannel);
+    pub fn brpc_partition_channel_init(
+        channel: *mut BrpcPartitionChannel,
+        num_partition_kinds: c_int,
+        parse: PartitionParserFn,
+        parse_arg: *mut c_void,
+        drop_parse_arg: extern "C" fn(*mut c_void),
+        naming_service_url: *const c_char,
+        load_balancer_name: *const c_char,
+        options: *const BrpcPartitionChannelOptions,
+    ) -> c_int;
+    pub fn brpc_partition_channel_partition_count(channel: *mut BrpcPartitionChannel) -> c_int;
+    pub fn brpc_partition_channel_as_rpc_channel(
+        channel: *mut BrpcPartitionChannel,
+    ) -> *mut BrpcRpcChannel;
+
+    pub fn brpc_dynamic_partition_channel_new() -> *mut BrpcDynamicPartitionChannel;
+    pub fn brpc_dynamic_partition_channel_destroy(channel: *mut BrpcDynamicPartitionChannel);
+    pub fn brpc_dynamic_partition_channel_init(
+        channel: *mut BrpcDynamicPartitionChannel,
+        parse: PartitionParserFn,
+        parse_arg: *mut c_void,
+        drop_parse_arg: extern "C" fn(*mut c_void),
+        naming_service_url: *const c_char,
+        load_balancer_name: *const c_char,
+        options: *const BrpcPartitionChannelOptions,
+    ) -> c_int;
+    pub fn brpc_dynamic_partition_channel_as_rpc_channel(
+        channel: *mut BrpcDynamicPartitionChannel,
+    ) -> *mut BrpcRpcChannel;
+
+    pub fn brpc_partition_channel_options_new(
+        options: *const BrpcChannelOptions,
+    ) -> *mut BrpcPartitionChannelOptions;
+    pub fn brpc_partition_channel_options_destroy(options: *mut BrpcPartitionChannelOptions);
+    pub fn brpc_partition_channel_options_set_fail_limit(
+        options: *mut BrpcPartitionChannelOptions,
+        fail_limit: c_int,
+    );
+    pub fn brpc_partition_channel_options_set_succeed_without_server(
+        options: *mut BrpcPartitionChannelOptions,
+        succeed_without_server: c_int,
+    );
+    pub fn brpc_partition_channel_options_set_call_mapper(
+        options: *mut BrpcPartitionChannelOptions,
+        map: CallMapperFn,
+        map_arg: *mut c_void,
+        drop_map_arg: extern "C" fn(*mut c_void),
+    );
+    pub fn brpc_partition_channel_options_set_response_merger(
+        options: *mut BrpcPartitionChannelOptions,
+        merge: ResponseMergerFn,
+        merge_arg: *mut c_void,
+        drop_merge_arg: extern "C" fn(*mut c_void),
+    );
+
+    pub fn brpc_rust_channel_new(
+        call: RustChannelCallFn,
+        arg: *mut c_void,
+        drop: extern "C" fn(*mut c_void),
+    ) -> *mut BrpcRpcChannel;
+    pub fn brpc_rust_channel_destroy(channel: *mut BrpcRpcChannel);
+
+    pub fn brpc_retry_policy_new(
+        do_retry: RetryPolicyFn,
+        arg: *mut c_void,
+        drop_arg: extern "C" fn(*mut c_void),
+    ) -> *mut BrpcRetryPolicy;
+    pub fn brpc_retry_policy_destroy(policy: *mut BrpcRetryPolicy);
+
     pub fn brpc_controller_new() -> *mut BrpcController;
     pub fn brpc_controller_destroy(cntl: *mut BrpcController);
     pub fn brpc_controller_failed(cntl: *mut BrpcController) -> c_int;
     pub fn brpc_controller_error_code(cntl: *mut BrpcController) -> c_int;
-    pub fn brpc_controller_set_failed(cntl: *mut BrpcController, code: c_int);
+    pub fn brpc_controller_start_cancel(cntl: *mut BrpcController);
+    pub fn brpc_controller_set_failed(cntl: *mut BrpcController, code: c_int, text: *const c_char);
+    pub fn brpc_controller_error_text(cntl: *mut BrpcController) -> *mut c_char;
+    pub fn brpc_controller_retried_count(cntl: *mut BrpcController) -> c_int;
+    pub fn brpc_controller_log_id(cntl: *mut BrpcController) -> u64;
+    pub fn brpc_controller_set_timeout_ms(cntl: *mut BrpcController, timeout_ms: i64);
+    pub fn brpc_controller_set_max_retry(cntl: *mut BrpcController, max_retry: c_int);
+    pub fn brpc_controller_set_retry_policy(
+        cntl: *mut BrpcController,
+        policy: *mut BrpcRetryPolicy,
+    );
+    pub fn brpc_controller_set_log_id(cntl: *mut BrpcController, log_id: u64);
+    pub fn brpc_controller_set_request_code(cntl: *mut BrpcController, request_code: u64);
+    pub fn brpc_controller_set_backup_request_ms(cntl: *mut BrpcController, timeout_ms: i64);
+    pub fn brpc_controller_set_request_compress_type(
+        cntl: *mut BrpcController,
+        compress_type: c_int,
+    );
+    pub fn brpc_controller_latency_us(cntl: *mut BrpcController) -> i64;
+    pub fn brpc_controller_has_backup_request(cntl: *mut BrpcController) -> c_int;
+    pub fn brpc_controller_remote_side(cntl: *mut BrpcController, ip: *mut u32, port: *mut c_int);
+    pub fn brpc_controller_local_side(cntl: *mut BrpcController, ip: *mut u32, port: *mut c_int);
+    pub fn brpc_controller_http_header(
+        cntl: *mut BrpcController,
+        name: *const c_char,
+        len: *mut usize,
+    ) -> *const c_char;
+    pub fn brpc_controller_peer_certificate_subject(cntl: *mut BrpcController) -> *mut c_char;
+    pub fn brpc_controller_remaining_deadline_us(cntl: *mut BrpcController) -> i64;
+    pub fn brpc_controller_abort_on_panic(cntl: *mut BrpcController) -> c_int;
     pub fn brpc_controller_get_request_attachment(cntl: *mut BrpcController) -> *mut BrpcIOBuf;
     pub fn brpc_controller_get_response_attachment(cntl: *mut BrpcController) -> *mut BrpcIOBuf;
 }
@@ -61,8 +61,12 @@ bool ZeroCopyBuf::Advance(int count) {
     _total_len += to_skip;
     nc += to_skip;
     if (_block_start == _block_end) {
-      int block_len;
-      _stream.Next(reinterpret_cast<const void **>(_block_start), &block_len);
+      const void *start_ptr = NULL;
+      int block_len = 0;
+      if (!_stream.Next(&start_ptr, &block_len)) {
+        block_len = 0;
+      }
+      _block_start = (char *)start_ptr;
       _block_end = _block_start + block_len;
     }
   }
@@ -118,8 +122,10 @@ bool ZeroCopyBufMut::AdvanceMut(size_t count) {
     _total_len += to_skip;
     nc += to_skip;
     if (_block_start == _block_end) {
-      int block_len;
-      _stream.Next(reinterpret_cast<void **>(_block_start), &block_len);
+      int block_len = 0;
+      if (!_stream.Next(reinterpret_cast<void **>(&_block_start), &block_len)) {
+        return false;
+      }
       _block_end = _block_start + block_len;
     }
   }
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::ffi::BrpcIOBuf;
+use crate::ffi::{brpc_iobuf_destroy, brpc_iobuf_new, BrpcIOBuf};
 use bytes::{Buf, BufMut};
 use std::os::raw::{c_int, c_ulonglong, c_void}; // traits
 
@@ -98,6 +98,36 @@ impl BufMut for ZeroCopyBufMut {
     }
 }
 
+/// An owned `butil::IOBuf`, used to pass serialized messages between Rust and
+/// C++.
+pub struct IOBuf {
+    inner: *mut BrpcIOBuf,
+}
+
+impl IOBuf {
+    pub fn new() -> Self {
+        IOBuf {
+            inner: unsafe { brpc_iobuf_new() },
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut BrpcIOBuf {
+        self.inner
+    }
+}
+
+impl Default for IOBuf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IOBuf {
+    fn drop(&mut self) {
+        unsafe { brpc_iobuf_destroy(self.inner) }
+    }
+}
+
 extern "C" {
     pub fn zero_copy_buf_new(iobuf: *mut BrpcIOBuf) -> *mut BrpcZeroCopyBuf;
     pub fn zero_copy_buf_mut_new(iobuf: *mut BrpcIOBuf) -> *mut BrpcZeroCopyBufMut;
@@ -11,6 +11,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
+
 fn main() -> std::io::Result<()> {
-    brpc_build::compile_protos(&["echo/echo.proto"], &["echo"])
+    brpc_build::compile_protos(&["echo/echo.proto"], &["echo"])?;
+
+    // A stock C++ peer for the interoperability tests, and certificates for the
+    // TLS tests
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR env var is not set");
+    cc::Build::new()
+        .cpp(true)
+        .file("echo/cpp_peer.cpp")
+        .file("echo/tls_certs.cpp")
+        .include(&out_dir)
+        .flag("-std=c++11")
+        .warnings(false)
+        .compile("echo_cpp_peer");
+    // echo_cpp_peer depends on the messages compiled into brpc_service
+    println!("cargo:rustc-link-lib=static=brpc_service");
+    println!("cargo:rustc-link-lib=ssl");
+    println!("cargo:rustc-link-lib=crypto");
+    Ok(())
 }
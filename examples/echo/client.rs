@@ -21,8 +21,7 @@ pub mod echo {
 fn main() {
     let mut options = ChannelOptions::new();
     options.set_timeout_ms(100);
-    let addr = "127.0.0.1:50000".parse().expect("Invalid socket address");
-    let ch = Channel::with_options(&addr, &options);
+    let ch = Channel::new("127.0.0.1:50000", &options).expect("Failed to create channel");
     let client = echo::EchoServiceStub::with_channel(&ch);
     let request = echo::EchoRequest {
         message: "hello".to_owned(),
@@ -0,0 +1,108 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A stock C++ BRPC echo server and client, used to check that brpc-rs peers
+// interoperate with C++ peers over the same echo.proto.
+
+#include "echo.pb.h"
+#include <brpc/channel.h>
+#include <brpc/server.h>
+#include <string.h>
+
+namespace {
+class CppEchoServiceImpl : public example::EchoService {
+public:
+  void echo(google::protobuf::RpcController *cntl_base,
+            const example::EchoRequest *request,
+            example::EchoResponse *response,
+            google::protobuf::Closure *done) {
+    brpc::ClosureGuard done_guard(done);
+    response->set_message(request->message());
+  }
+};
+} // namespace
+
+extern "C" {
+brpc::Server *cpp_echo_server_start(int port) {
+  brpc::Server *server = new brpc::Server;
+  if (server->AddService(new CppEchoServiceImpl, brpc::SERVER_OWNS_SERVICE) !=
+          0 ||
+      server->Start(port, NULL) != 0) {
+    delete server;
+    return NULL;
+  }
+  return server;
+}
+
+// Like cpp_echo_server_start(), over TLS with the PEM `certificate` and
+// `private_key`. Clients must present a certificate signed by the CA in
+// `client_ca_file` unless it is NULL.
+brpc::Server *cpp_echo_server_start_ssl(int port, const char *certificate,
+                                        const char *private_key,
+                                        const char *client_ca_file) {
+  brpc::ServerOptions options;
+  brpc::ServerSSLOptions *ssl_options = options.mutable_ssl_options();
+  ssl_options->default_cert.certificate = certificate;
+  ssl_options->default_cert.private_key = private_key;
+  if (client_ca_file != NULL) {
+    ssl_options->verify.verify_depth = 1;
+    ssl_options->verify.ca_file_path = client_ca_file;
+  }
+  brpc::Server *server = new brpc::Server;
+  if (server->AddService(new CppEchoServiceImpl, brpc::SERVER_OWNS_SERVICE) !=
+          0 ||
+      server->Start(port, &options) != 0) {
+    delete server;
+    return NULL;
+  }
+  return server;
+}
+
+// The port `server` listens on, picked by the system when started on port 0
+int cpp_echo_server_port(brpc::Server *server) {
+  return server->listen_address().port;
+}
+
+void cpp_echo_server_stop(brpc::Server *server) {
+  server->Stop(0);
+  server->Join();
+  delete server;
+}
+
+// Send `message` to the echo server at 127.0.0.1:`port` over `protocol` and
+// copy the echoed message into `out`. Returns 0 on success.
+int cpp_echo_client_call(int port, const char *protocol, const char *message,
+                         char *out, size_t out_len) {
+  brpc::ChannelOptions options;
+  options.protocol = protocol;
+  options.timeout_ms = 1000;
+  brpc::Channel channel;
+  if (channel.Init("127.0.0.1", port, &options) != 0) {
+    return -1;
+  }
+  example::EchoService_Stub stub(&channel);
+  example::EchoRequest request;
+  example::EchoResponse response;
+  brpc::Controller cntl;
+  request.set_message(message);
+  stub.echo(&cntl, &request, &response, NULL);
+  if (cntl.Failed()) {
+    return cntl.ErrorCode();
+  }
+  if (response.message().size() >= out_len) {
+    return -1;
+  }
+  strncpy(out, response.message().c_str(), out_len);
+  return 0;
+}
+}
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use brpc_rs::{Server, ServerOptions, ServiceOwnership};
+use brpc_rs::{Server, ServerOptions};
 
 pub mod echo {
     include!(concat!(env!("OUT_DIR"), "/example.rs"));
@@ -20,7 +20,7 @@ pub mod echo {
 
 fn main() {
     let mut service = echo::EchoService::new();
-    service.set_echo_handler(&mut move |request, mut response| {
+    service.set_echo_handler(|_ctx, request, response| {
         response.message = request.message.clone();
         Ok(())
     });
@@ -28,9 +28,7 @@ fn main() {
     let mut server = Server::new();
     let mut options = ServerOptions::new();
     options.set_idle_timeout_ms(1000);
-    server
-        .add_service(&service, ServiceOwnership::ServerDoesntOwnService)
-        .expect("Failed to add service");
+    server.add_service(service).expect("Failed to add service");
     server
         .start(50000, &options)
         .expect("Failed to start service");
@@ -0,0 +1,107 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Self-signed certificates made at test time, so that the TLS tests run
+// offline.
+
+#include <openssl/bn.h>
+#include <openssl/evp.h>
+#include <openssl/pem.h>
+#include <openssl/rsa.h>
+#include <openssl/x509.h>
+#include <openssl/x509v3.h>
+#include <string.h>
+
+#include <string>
+
+namespace {
+bool add_extension(X509 *x509, int nid, const char *value) {
+  X509V3_CTX ctx;
+  X509V3_set_ctx_nodb(&ctx);
+  X509V3_set_ctx(&ctx, x509, x509, NULL, NULL, 0);
+  X509_EXTENSION *ext = X509V3_EXT_conf_nid(NULL, &ctx, nid, value);
+  if (ext == NULL) {
+    return false;
+  }
+  bool ok = X509_add_ext(x509, ext, -1);
+  X509_EXTENSION_free(ext);
+  return ok;
+}
+
+// Copy the contents of `bio` into `out` as a C string
+bool copy_bio(BIO *bio, char *out, size_t out_len) {
+  char *data = NULL;
+  long len = BIO_get_mem_data(bio, &data);
+  if (len < 0 || (size_t)len >= out_len) {
+    return false;
+  }
+  memcpy(out, data, len);
+  out[len] = '\0';
+  return true;
+}
+} // namespace
+
+extern "C" {
+// Make a self-signed certificate for `common_name`, which can also be used as
+// its own CA, and copy it and its private key into `cert` and `key` in PEM
+// format. Returns 0 on success.
+int tls_self_signed_cert(const char *common_name, char *cert, size_t cert_len,
+                         char *key, size_t key_len) {
+  static long serial = 1;
+  int ret = -1;
+  EVP_PKEY *pkey = EVP_PKEY_new();
+  RSA *rsa = RSA_new();
+  BIGNUM *e = BN_new();
+  X509 *x509 = X509_new();
+  BIO *cert_bio = BIO_new(BIO_s_mem());
+  BIO *key_bio = BIO_new(BIO_s_mem());
+  std::string san = std::string("DNS:") + common_name;
+  X509_NAME *name = NULL;
+
+  if (!BN_set_word(e, RSA_F4) || !RSA_generate_key_ex(rsa, 2048, e, NULL) ||
+      !EVP_PKEY_assign_RSA(pkey, rsa)) {
+    goto out;
+  }
+  rsa = NULL; // owned by pkey
+
+  X509_set_version(x509, 2);
+  ASN1_INTEGER_set(X509_get_serialNumber(x509), __sync_fetch_and_add(&serial, 1));
+  X509_gmtime_adj(X509_get_notBefore(x509), -3600);
+  X509_gmtime_adj(X509_get_notAfter(x509), 24 * 3600);
+  X509_set_pubkey(x509, pkey);
+  name = X509_get_subject_name(x509);
+  X509_NAME_add_entry_by_txt(name, "CN", MBSTRING_ASC,
+                             (const unsigned char *)common_name, -1, -1, 0);
+  X509_set_issuer_name(x509, name);
+  if (!add_extension(x509, NID_basic_constraints, "critical,CA:TRUE") ||
+      !add_extension(x509, NID_subject_alt_name, san.c_str()) ||
+      !X509_sign(x509, pkey, EVP_sha256())) {
+    goto out;
+  }
+
+  if (PEM_write_bio_X509(cert_bio, x509) &&
+      PEM_write_bio_PrivateKey(key_bio, pkey, NULL, NULL, 0, NULL, NULL) &&
+      copy_bio(cert_bio, cert, cert_len) && copy_bio(key_bio, key, key_len)) {
+    ret = 0;
+  }
+
+out:
+  BIO_free(key_bio);
+  BIO_free(cert_bio);
+  X509_free(x509);
+  BN_free(e);
+  RSA_free(rsa);
+  EVP_PKEY_free(pkey);
+  return ret;
+}
+}
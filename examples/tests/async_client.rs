@@ -0,0 +1,91 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, RpcStatus};
+use std::error::Error;
+use std::os::raw::{c_int, c_void};
+
+mod common;
+
+use common::block_on;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// echo/cpp_peer.cpp
+extern "C" {
+    fn cpp_echo_server_start(port: c_int) -> *mut c_void;
+    fn cpp_echo_server_port(server: *mut c_void) -> c_int;
+    fn cpp_echo_server_stop(server: *mut c_void);
+}
+
+#[test]
+fn concurrent_async_calls() {
+    let server = unsafe { cpp_echo_server_start(0) };
+    assert!(!server.is_null());
+    let port = unsafe { cpp_echo_server_port(server) };
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    // All RPCs are in flight before the first one is awaited.
+    let futures = (0..32)
+        .map(|i| {
+            let request = echo::EchoRequest {
+                message: format!("hello {}", i),
+            };
+            client.echo_async(&request)
+        })
+        .collect::<Vec<_>>();
+    for (i, future) in futures.into_iter().enumerate() {
+        let response = block_on(future).expect("echo failed");
+        assert_eq!(format!("hello {}", i), response.message);
+    }
+
+    unsafe { cpp_echo_server_stop(server) };
+}
+
+#[test]
+fn async_call_to_unreachable_server() {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(100);
+    let ch = Channel::new(&format!("127.0.0.1:{}", common::unused_port()), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let status = block_on(client.echo_async(&request)).unwrap_err();
+    assert!(status.is_connection_error() || status.is_timeout());
+    assert!(!status.message.is_empty());
+}
+
+#[test]
+fn call_error_composes_with_question_mark() {
+    fn call() -> Result<String, Box<dyn Error>> {
+        let mut options = ChannelOptions::new();
+        options.set_timeout_ms(100);
+        let ch = Channel::new(&format!("127.0.0.1:{}", common::unused_port()), &options)?;
+        let client = echo::EchoServiceStub::with_channel(&ch);
+        let request = echo::EchoRequest {
+            message: "hello".to_owned(),
+        };
+        Ok(client.echo(&request)?.message)
+    }
+    let error = call().unwrap_err();
+    let status = error.downcast_ref::<RpcStatus>().unwrap();
+    assert_eq!(status.to_string(), error.to_string());
+}
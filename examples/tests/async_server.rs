@@ -0,0 +1,101 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{BrpcError, RpcStatus, Server, ServerOptions};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::thread;
+use std::time::Duration;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// echo/cpp_peer.cpp
+extern "C" {
+    fn cpp_echo_client_call(
+        port: c_int,
+        protocol: *const c_char,
+        message: *const c_char,
+        out: *mut c_char,
+        out_len: usize,
+    ) -> c_int;
+}
+
+fn call(port: c_int, message: &str) -> Result<String, c_int> {
+    let protocol = CString::new("baidu_std").unwrap();
+    let c_message = CString::new(message).unwrap();
+    let mut out = vec![0 as c_char; 256];
+    let ret = unsafe {
+        cpp_echo_client_call(
+            port,
+            protocol.as_ptr(),
+            c_message.as_ptr(),
+            out.as_mut_ptr(),
+            out.len(),
+        )
+    };
+    if ret != 0 {
+        return Err(ret);
+    }
+    let echoed = unsafe { CStr::from_ptr(out.as_ptr()) };
+    Ok(echoed.to_str().unwrap().to_owned())
+}
+
+#[test]
+fn respond_from_another_thread() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_async_handler(|request, responder| {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            let response = echo::EchoResponse {
+                message: request.message,
+            };
+            responder.respond(Ok(response));
+        });
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port() as c_int;
+
+    let handles = (0..8)
+        .map(|i| thread::spawn(move || call(port, &format!("hello {}", i))))
+        .collect::<Vec<_>>();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(Ok(format!("hello {}", i)), handle.join().unwrap());
+    }
+}
+
+#[test]
+fn respond_with_error_or_not_at_all() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_async_handler(|request, responder| {
+        if request.message == "fail" {
+            responder.respond(Err(RpcStatus::new(10001, "rejected")));
+        }
+        // Otherwise the responder is dropped without responding
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port() as c_int;
+
+    assert_eq!(Err(10001), call(port, "fail"));
+    assert_eq!(Err(BrpcError::EINTERNAL as c_int), call(port, "drop"));
+}
@@ -0,0 +1,114 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, Controller, Server};
+use std::sync::{Arc, RwLock};
+
+mod common;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// An echo server which, given a `gate`, responds only once the gate is no
+// longer write-locked
+fn serve(gate: Option<Arc<RwLock<()>>>) -> Server {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, request, response| {
+        if let Some(gate) = &gate {
+            let _open = gate.read().unwrap();
+        }
+        response.message = request.message.clone();
+        Ok(())
+    });
+    common::serve(service)
+}
+
+// Call both servers of `url` in turn, returning for each call whether a
+// backup request was sent and the port of the server which responded, if any
+fn call_both(
+    url: &str,
+    options: &ChannelOptions,
+    backup_request_ms: Option<i64>,
+) -> Vec<(bool, Option<u16>)> {
+    let ch = Channel::new_with_naming_service(url, "rr", options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let mut calls: Vec<_> = (0..2)
+        .map(|_| {
+            let mut cntl = Controller::new();
+            if let Some(backup_request_ms) = backup_request_ms {
+                cntl.set_backup_request_ms(backup_request_ms);
+            }
+            let port = client
+                .echo_with(&mut cntl, &request)
+                .ok()
+                .and_then(|_| cntl.remote_side())
+                .map(|addr| addr.port());
+            (cntl.has_backup_request(), port)
+        })
+        .collect();
+    calls.sort();
+    calls
+}
+
+// The fast server responded to every call, backing up at least the one to the
+// slow server. The call to the fast server may be backed up too if it is slow
+// to respond.
+fn assert_backed_up(calls: &[(bool, Option<u16>)], fast_port: u16) {
+    assert!(calls.iter().all(|&(_, port)| port == Some(fast_port)));
+    assert!(calls
+        .iter()
+        .any(|&(has_backup_request, _)| has_backup_request));
+}
+
+#[test]
+fn backup_request() {
+    let gate = Arc::new(RwLock::new(()));
+    let slow = serve(Some(gate.clone()));
+    let fast = serve(None);
+    let url = format!(
+        "list://127.0.0.1:{},127.0.0.1:{}",
+        slow.listen_port(),
+        fast.listen_port()
+    );
+    let fast_port = fast.listen_port();
+    // The slow server responds to nothing until the gate is dropped, which
+    // happens before the servers are stopped as it is declared after them
+    let _closed = gate.write().unwrap();
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    // A backup request uses up a retry, and none is sent with no retry left.
+    // Timeouts are never retried.
+    options.set_max_retry(1);
+
+    // Without backup requests the slow server times out
+    let calls = call_both(&url, &options, None);
+    assert_eq!(vec![(false, None), (false, Some(fast_port))], calls);
+
+    // Set per call, the slow server is backed up by the fast one
+    let calls = call_both(&url, &options, Some(50));
+    assert_backed_up(&calls, fast_port);
+
+    // Set for the channel
+    options.set_backup_request_ms(50);
+    let calls = call_both(&url, &options, None);
+    assert_backed_up(&calls, fast_port);
+
+    // The call overrides the channel
+    let calls = call_both(&url, &options, Some(-1));
+    assert_eq!(vec![(false, None), (false, Some(fast_port))], calls);
+}
@@ -0,0 +1,70 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, CompressType, Controller, Server, ServerOptions,
+};
+use std::net::{SocketAddr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+#[test]
+fn call_with_controller() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(|ctx, request, response| {
+        if request.message == "slow" {
+            thread::sleep(Duration::from_millis(300));
+        }
+        response.message = format!("{}", ctx.log_id());
+        Ok(())
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let addr: SocketAddrV4 = format!("127.0.0.1:{}", port).parse().unwrap();
+    let ch = Channel::new(&addr, &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let mut cntl = Controller::new();
+    cntl.set_log_id(42);
+    cntl.set_request_compress_type(CompressType::Gzip);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let response = client.echo_with(&mut cntl, &request).expect("echo failed");
+    assert_eq!("42", response.message);
+    assert_eq!(Some(SocketAddr::V4(addr)), cntl.remote_side());
+    assert_eq!(0, cntl.retried_count());
+    assert!(cntl.latency() > Duration::from_micros(0));
+
+    // The timeout of the call overrides the one of the channel
+    let mut cntl = Controller::new();
+    cntl.set_timeout_ms(100);
+    cntl.set_max_retry(0);
+    let request = echo::EchoRequest {
+        message: "slow".to_owned(),
+    };
+    let status = client.echo_with(&mut cntl, &request).unwrap_err();
+    assert_eq!(BrpcError::ERPCTIMEDOUT, status.error());
+}
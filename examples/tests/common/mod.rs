@@ -0,0 +1,188 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use brpc_rs::{
+    CallMapper, MergeResult, Partition, PartitionParser, ResponseMerger, Server, ServerOptions,
+    Service, SubCall,
+};
+use prost::Message;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::future::Future;
+use std::net::TcpListener;
+use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll `future` on the current thread until it is ready
+pub fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+// Messages of example.proto, which the fixtures below handle as bytes
+mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+}
+
+/// A `Server` of `service` started on a port picked by the kernel, see
+/// `Server::listen_port()`
+pub fn serve<T: Service + 'static>(service: T) -> Server {
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    server
+}
+
+/// Parses partition tags such as "1/2"
+pub struct Fraction;
+
+impl PartitionParser for Fraction {
+    fn parse(&self, tag: &str) -> Option<Partition> {
+        let mut parts = tag.splitn(2, '/');
+        Some(Partition {
+            index: parts.next()?.parse().ok()?,
+            num_partition_kinds: parts.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Tags an `EchoRequest` with the index of the sub-channel, skipping the ones
+/// listed
+#[derive(Default)]
+pub struct Shard {
+    pub skip: Vec<usize>,
+}
+
+impl CallMapper for Shard {
+    fn map(&self, channel_index: usize, method: &str, request: &[u8]) -> SubCall {
+        assert_eq!("example.EchoService.echo", method);
+        if self.skip.contains(&channel_index) {
+            return SubCall::Skip;
+        }
+        let mut request = echo::EchoRequest::decode(request).unwrap();
+        request.message = format!("{}#{}", request.message, channel_index);
+        let mut sub_request = Vec::new();
+        request.encode(&mut sub_request).unwrap();
+        SubCall::Request(sub_request)
+    }
+}
+
+/// Joins the messages of all `EchoResponse`s with commas
+pub struct Join;
+
+impl ResponseMerger for Join {
+    fn merge(&self, response: &mut Vec<u8>, sub_response: &[u8]) -> MergeResult {
+        let mut merged = echo::EchoResponse::decode(&response[..]).unwrap();
+        let sub_response = echo::EchoResponse::decode(sub_response).unwrap();
+        if !merged.message.is_empty() {
+            merged.message.push(',');
+        }
+        merged.message.push_str(&sub_response.message);
+        response.clear();
+        merged.encode(response).unwrap();
+        MergeResult::Merged
+    }
+}
+
+/// A local port which nothing listens on
+pub fn unused_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+// echo/tls_certs.cpp
+extern "C" {
+    fn tls_self_signed_cert(
+        common_name: *const c_char,
+        cert: *mut c_char,
+        cert_len: usize,
+        key: *mut c_char,
+        key_len: usize,
+    ) -> c_int;
+}
+
+/// A self-signed certificate in PEM, which is also its own CA
+pub struct Cert {
+    pub certificate: String,
+    pub private_key: String,
+    ca_file: PathBuf,
+}
+
+impl Cert {
+    pub fn generate(common_name: &str) -> Cert {
+        let name = CString::new(common_name).unwrap();
+        let mut certificate = vec![0u8; 8192];
+        let mut private_key = vec![0u8; 8192];
+        let rc = unsafe {
+            tls_self_signed_cert(
+                name.as_ptr(),
+                certificate.as_mut_ptr() as *mut c_char,
+                certificate.len(),
+                private_key.as_mut_ptr() as *mut c_char,
+                private_key.len(),
+            )
+        };
+        assert_eq!(0, rc, "failed to generate a certificate");
+        let to_string = |pem: &[u8]| {
+            let len = pem.iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(pem[..len].to_vec()).unwrap()
+        };
+        let certificate = to_string(&certificate);
+        let ca_file = env::temp_dir().join(format!(
+            "brpc-rs-tls-{}-{}.pem",
+            std::process::id(),
+            common_name
+        ));
+        fs::write(&ca_file, &certificate).unwrap();
+        Cert {
+            certificate,
+            private_key: to_string(&private_key),
+            ca_file,
+        }
+    }
+
+    pub fn ca_file(&self) -> &str {
+        self.ca_file.to_str().unwrap()
+    }
+}
+
+impl Drop for Cert {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.ca_file);
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, Limiter, RpcResult, Server, ServerContext, ServerOptions,
+};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+use common::block_on;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+struct SlowHandler;
+
+impl echo::EchoServiceHandler for SlowHandler {
+    fn echo(
+        &self,
+        _ctx: &mut ServerContext,
+        request: &echo::EchoRequest,
+    ) -> RpcResult<echo::EchoResponse> {
+        thread::sleep(Duration::from_millis(200));
+        Ok(echo::EchoResponse {
+            message: request.message.clone(),
+        })
+    }
+}
+
+/// Sends `n` calls at the same time, and returns how many succeeded and how
+/// many were rejected with `BrpcError::ELIMIT`.
+fn concurrent_calls(port: u16, n: usize) -> (usize, usize) {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(2000);
+    options.set_max_retry(0);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let futures = (0..n)
+        .map(|_| client.echo_async(&request))
+        .collect::<Vec<_>>();
+    let (mut succeeded, mut rejected) = (0, 0);
+    for future in futures {
+        match block_on(future) {
+            Ok(_) => succeeded += 1,
+            Err(status) => {
+                assert_eq!(BrpcError::ELIMIT, status.error(), "{}", status);
+                rejected += 1;
+            }
+        }
+    }
+    (succeeded, rejected)
+}
+
+#[test]
+fn method_max_concurrency() {
+    let mut server = Server::new();
+    server
+        .add_service(echo::EchoService::from_handler(SlowHandler))
+        .expect("Failed to add service");
+    server
+        .set_method_max_concurrency("EchoService", "echo", Limiter::Constant(1))
+        .expect("Failed to limit echo");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let (succeeded, rejected) = concurrent_calls(port, 4);
+    assert!(succeeded >= 1);
+    assert!(rejected >= 1);
+}
+
+#[test]
+fn server_max_concurrency() {
+    let mut server = Server::new();
+    server
+        .add_service(echo::EchoService::from_handler(SlowHandler))
+        .expect("Failed to add service");
+    let mut options = ServerOptions::new();
+    options.set_num_threads(4);
+    options.set_max_concurrency(2);
+    server.start(0, &options).expect("Failed to start service");
+    let port = server.listen_port();
+
+    let (succeeded, rejected) = concurrent_calls(port, 6);
+    assert!(succeeded >= 2);
+    assert!(rejected >= 1);
+}
+
+#[test]
+fn set_method_max_concurrency_errors() {
+    let mut server = Server::new();
+    server
+        .add_service(echo::EchoService::from_handler(SlowHandler))
+        .expect("Failed to add service");
+    assert_eq!(
+        Err(BrpcError::ENOSERVICE),
+        server.set_method_max_concurrency("NoService", "echo", Limiter::Auto)
+    );
+    assert_eq!(
+        Err(BrpcError::ENOMETHOD),
+        server.set_method_max_concurrency("EchoService", "no_method", Limiter::Auto)
+    );
+    server
+        .set_method_max_concurrency("example.EchoService", "echo", Limiter::Auto)
+        .expect("Failed to limit echo");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    // Limits are fixed once the server is started
+    assert!(server
+        .set_method_max_concurrency("EchoService", "echo", Limiter::Constant(1))
+        .is_err());
+    let (succeeded, _) = concurrent_calls(port, 1);
+    assert_eq!(1, succeeded);
+}
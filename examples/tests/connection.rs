@@ -0,0 +1,108 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, ConnectionType, Server};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+use common::block_on;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+type Clients = Arc<Mutex<HashSet<SocketAddr>>>;
+
+// An echo server which records the addresses it was called from
+fn serve(clients: &Clients) -> Server {
+    let clients = clients.clone();
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |ctx, request, response| {
+        clients.lock().unwrap().insert(ctx.remote_side().unwrap());
+        thread::sleep(Duration::from_millis(50));
+        response.message = request.message.clone();
+        Ok(())
+    });
+    common::serve(service)
+}
+
+fn channel(port: u16, connection_type: Option<ConnectionType>, group: &str) -> Channel {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_connect_timeout_ms(500);
+    if let Some(connection_type) = connection_type {
+        options.set_connection_type(connection_type);
+    }
+    options.set_connection_group(group).unwrap();
+    let endpoint = format!("127.0.0.1:{}", port);
+    Channel::new(&endpoint, &options).expect("Failed to create channel")
+}
+
+// Number of connections the server was called over, for 4 concurrent calls
+// over each of `channels`
+fn connections(channels: &[Channel], clients: &Clients) -> usize {
+    clients.lock().unwrap().clear();
+    let stubs: Vec<_> = channels
+        .iter()
+        .map(echo::EchoServiceStub::with_channel)
+        .collect();
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let request = &request;
+    let futures: Vec<_> = stubs
+        .iter()
+        .flat_map(|stub| (0..4).map(move |_| stub.echo_async(request)))
+        .collect();
+    for future in futures {
+        block_on(future).expect("echo failed");
+    }
+    let connections = clients.lock().unwrap().len();
+    connections
+}
+
+#[test]
+fn connection_types() {
+    let clients = Clients::default();
+    let server = serve(&clients);
+    let port = server.listen_port();
+
+    // Concurrent calls share a single connection
+    let ch = channel(port, Some(ConnectionType::Single), "");
+    assert_eq!(1, connections(&[ch], &clients));
+
+    // Unless the channels are in different groups
+    let chs = [
+        channel(port, Some(ConnectionType::Single), "first"),
+        channel(port, Some(ConnectionType::Single), "second"),
+    ];
+    assert_eq!(2, connections(&chs, &clients));
+
+    // Concurrent calls each take a connection from the pool, which calls
+    // that did not overlap may have shared
+    let ch = channel(port, Some(ConnectionType::Pooled), "");
+    let pooled = connections(&[ch], &clients);
+    assert!(1 < pooled && pooled <= 4, "{} pooled connections", pooled);
+
+    // Each call makes a connection of its own, although the client may reuse
+    // the port of a closed one
+    let ch = channel(port, Some(ConnectionType::Short), "");
+    let short = connections(&[ch], &clients);
+    assert!(1 < short && short <= 4, "{} short connections", short);
+}
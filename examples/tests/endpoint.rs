@@ -0,0 +1,65 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{BrpcError, Channel, ChannelOptions, Server, ServerOptions};
+use std::net::SocketAddrV4;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+fn call(ch: &Channel) {
+    let client = echo::EchoServiceStub::with_channel(ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!(request.message, response.message);
+}
+
+#[test]
+fn channel_to_endpoint() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(|_ctx, request, response| {
+        response.message = request.message.clone();
+        Ok(())
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let addr: SocketAddrV4 = format!("127.0.0.1:{}", port).parse().unwrap();
+    call(&Channel::new(&addr, &options).expect("Failed to create channel"));
+    call(
+        &Channel::new(&format!("localhost:{}", port), &options).expect("Failed to create channel"),
+    );
+
+    let err = Channel::new("localhost", &options).err();
+    assert_eq!(Some(BrpcError::ECHANNEL), err);
+}
+
+#[test]
+fn unsupported_endpoints() {
+    // Only strings can name them, a `SocketAddrV6` is not a `ToEndpoint`
+    let options = ChannelOptions::new();
+    let err = Channel::new("[::1]:50139", &options).err();
+    assert_eq!(Some(BrpcError::ECHANNEL), err);
+    let err = Channel::new("unix:/tmp/brpc-rs.sock", &options).err();
+    assert_eq!(Some(BrpcError::ECHANNEL), err);
+}
@@ -0,0 +1,96 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, Protocol, Server, ServerOptions};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// echo/cpp_peer.cpp
+extern "C" {
+    fn cpp_echo_server_start(port: c_int) -> *mut c_void;
+    fn cpp_echo_server_port(server: *mut c_void) -> c_int;
+    fn cpp_echo_server_stop(server: *mut c_void);
+    fn cpp_echo_client_call(
+        port: c_int,
+        protocol: *const c_char,
+        message: *const c_char,
+        out: *mut c_char,
+        out_len: usize,
+    ) -> c_int;
+}
+
+#[test]
+fn rust_client_cpp_server() {
+    let server = unsafe { cpp_echo_server_start(0) };
+    assert!(!server.is_null());
+    let port = unsafe { cpp_echo_server_port(server) };
+
+    for protocol in &[
+        Protocol::BaiduStd,
+        Protocol::Http,
+        Protocol::H2,
+        Protocol::Grpc,
+    ] {
+        let mut options = ChannelOptions::new();
+        options.set_protocol(*protocol);
+        options.set_timeout_ms(1000);
+        let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+        let client = echo::EchoServiceStub::with_channel(&ch);
+        let request = echo::EchoRequest {
+            message: format!("hello over {:?}", protocol),
+        };
+        let response = client.echo(&request).expect("echo failed");
+        assert_eq!(request.message, response.message);
+    }
+
+    unsafe { cpp_echo_server_stop(server) };
+}
+
+#[test]
+fn cpp_client_rust_server() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(|_ctx, request, response| {
+        response.message = request.message.clone();
+        Ok(())
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port() as c_int;
+
+    for protocol in &["baidu_std", "http", "h2", "h2:grpc"] {
+        let message = format!("hello over {}", protocol);
+        let protocol = CString::new(*protocol).unwrap();
+        let c_message = CString::new(message.clone()).unwrap();
+        let mut out = vec![0 as c_char; 256];
+        let ret = unsafe {
+            cpp_echo_client_call(
+                port,
+                protocol.as_ptr(),
+                c_message.as_ptr(),
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+        assert_eq!(0, ret);
+        let echoed = unsafe { CStr::from_ptr(out.as_ptr()) };
+        assert_eq!(message, echoed.to_str().unwrap());
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{BrpcError, ChannelBase, MockChannel, RpcStatus};
+use prost::Message;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// A channel which echoes requests, failing empty ones
+fn echo_channel() -> MockChannel {
+    MockChannel::new(|method, request| {
+        assert_eq!("example.EchoService.echo", method);
+        let request = echo::EchoRequest::decode(request).unwrap();
+        if request.message.is_empty() {
+            return Err(RpcStatus::new(10001, "empty message"));
+        }
+        let response = echo::EchoResponse {
+            message: request.message,
+        };
+        let mut data = Vec::new();
+        response.encode(&mut data).unwrap();
+        Ok(data)
+    })
+}
+
+// Client code which does not depend on the kind of channel
+fn echo(ch: &dyn ChannelBase, message: &str) -> Result<String, RpcStatus> {
+    let client = echo::EchoServiceStub::with_channel(ch);
+    let request = echo::EchoRequest {
+        message: message.to_owned(),
+    };
+    client.echo(&request).map(|response| response.message)
+}
+
+#[test]
+fn call_over_mock_channel() {
+    let ch = echo_channel();
+    assert_eq!("hello", echo(&ch, "hello").unwrap());
+
+    let status = echo(&ch, "").unwrap_err();
+    assert_eq!(10001, status.code);
+    assert_eq!("empty message", status.message);
+}
+
+#[test]
+fn panic_in_mock_channel() {
+    let ch = MockChannel::new(|_method, _request| panic!("mock asked to panic"));
+    let status = echo(&ch, "hello").unwrap_err();
+    assert_eq!(BrpcError::EPANIC, status.error());
+}
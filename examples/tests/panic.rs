@@ -0,0 +1,76 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{BrpcError, Channel, ChannelOptions, Server, ServerOptions};
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+fn check_panic_isolated(service: echo::EchoService) {
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    // The server keeps serving after a handler panicked
+    for _ in 0..2 {
+        let request = echo::EchoRequest {
+            message: "panic".to_owned(),
+        };
+        let status = client.echo(&request).unwrap_err();
+        assert_eq!(BrpcError::EPANIC, status.error());
+
+        let request = echo::EchoRequest {
+            message: "hello".to_owned(),
+        };
+        let response = client.echo(&request).expect("echo failed");
+        assert_eq!(request.message, response.message);
+    }
+}
+
+#[test]
+fn panic_in_handler() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(|_ctx, request, response| {
+        if request.message == "panic" {
+            panic!("handler asked to panic");
+        }
+        response.message = request.message.clone();
+        Ok(())
+    });
+    check_panic_isolated(service);
+}
+
+#[test]
+fn panic_in_async_handler() {
+    let mut service = echo::EchoService::new();
+    service.set_echo_async_handler(|request, responder| {
+        if request.message == "panic" {
+            panic!("handler asked to panic");
+        }
+        let response = echo::EchoResponse {
+            message: request.message,
+        };
+        responder.respond(Ok(response));
+    });
+    check_panic_isolated(service);
+}
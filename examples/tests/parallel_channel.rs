@@ -0,0 +1,115 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, ParallelChannel, ParallelChannelOptions, RpcStatus, Server,
+};
+use std::sync::{Arc, RwLock};
+
+mod common;
+
+use common::{Join, Shard};
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// An echo server which, given a `gate`, fails requests for "fail" and holds
+// requests for "slow" while the gate is write-locked
+fn serve(gate: Option<Arc<RwLock<()>>>) -> Server {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, request, response| {
+        if let Some(gate) = &gate {
+            if request.message.starts_with("fail") {
+                return Err(RpcStatus::new(10001, "asked to fail"));
+            }
+            if request.message.starts_with("slow") {
+                let _open = gate.read().unwrap();
+            }
+        }
+        response.message = request.message.clone();
+        Ok(())
+    });
+    common::serve(service)
+}
+
+fn parallel_channel(
+    ports: &[u16],
+    options: &ParallelChannelOptions,
+    skip: &[usize],
+) -> ParallelChannel {
+    let mut pchan = ParallelChannel::with_options(options).unwrap();
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    for port in ports {
+        let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+        let mapper = Shard {
+            skip: skip.to_vec(),
+        };
+        pchan
+            .add_channel(ch, Some(Box::new(mapper)), Some(Box::new(Join)))
+            .expect("Failed to add channel");
+    }
+    pchan
+}
+
+fn echo(pchan: &ParallelChannel, message: &str) -> Result<Vec<String>, RpcStatus> {
+    let client = echo::EchoServiceStub::with_channel(pchan);
+    let request = echo::EchoRequest {
+        message: message.to_owned(),
+    };
+    let response = client.echo(&request)?;
+    let mut messages: Vec<_> = response.message.split(',').map(str::to_owned).collect();
+    messages.sort();
+    Ok(messages)
+}
+
+#[test]
+fn fan_out() {
+    let gate = Arc::new(RwLock::new(()));
+    let servers = [serve(None), serve(Some(gate.clone()))];
+    let ports: Vec<_> = servers.iter().map(Server::listen_port).collect();
+    // Dropped before the servers are stopped, as it is declared after them
+    let _closed = gate.write().unwrap();
+    let mut options = ParallelChannelOptions::new();
+    options.set_timeout_ms(1000);
+
+    // Fan out to all sub-channels and merge the responses
+    let pchan = parallel_channel(&ports, &options, &[]);
+    assert_eq!(2, pchan.channel_count());
+    assert_eq!(vec!["hello#0", "hello#1"], echo(&pchan, "hello").unwrap());
+
+    // Skipped sub-channels are not called
+    let pchan = parallel_channel(&ports, &options, &[1]);
+    assert_eq!(vec!["hello#0"], echo(&pchan, "hello").unwrap());
+
+    // By default one failed sub-call does not fail the call
+    let pchan = parallel_channel(&ports, &options, &[]);
+    assert_eq!(vec!["fail#0"], echo(&pchan, "fail").unwrap());
+
+    // Unless it reaches the fail limit
+    options.set_fail_limit(1);
+    let pchan = parallel_channel(&ports, &options, &[]);
+    let status = echo(&pchan, "fail").unwrap_err();
+    assert_eq!(BrpcError::ETOOMANYFAILS, status.error());
+    options.set_fail_limit(0);
+
+    // The call finishes once enough sub-calls have succeeded. The held
+    // sub-call could only time out, which would reach the fail limit, but it
+    // is canceled without counting as a failure.
+    options.set_fail_limit(1);
+    options.set_success_limit(1);
+    let pchan = parallel_channel(&ports, &options, &[]);
+    assert_eq!(vec!["slow#0"], echo(&pchan, "slow").unwrap());
+}
@@ -0,0 +1,92 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    ChannelOptions, DynamicPartitionChannel, PartitionChannel, PartitionChannelOptions, Server,
+};
+use std::env;
+use std::fs;
+
+mod common;
+
+use common::{Fraction, Join, Shard};
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// An echo server of the partition `index`
+fn serve(index: usize) -> Server {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, request, response| {
+        response.message = format!("{}@{}", request.message, index);
+        Ok(())
+    });
+    common::serve(service)
+}
+
+// A file naming service listing the servers with their partitions
+fn naming_service_url(servers: &[Server]) -> String {
+    let path = env::temp_dir().join("brpc_rs_partition_channel_servers");
+    let servers: String = servers
+        .iter()
+        .enumerate()
+        .map(|(index, server)| {
+            format!(
+                "127.0.0.1:{} {}/{}\n",
+                server.listen_port(),
+                index,
+                servers.len()
+            )
+        })
+        .collect();
+    fs::write(&path, servers).expect("Failed to write servers");
+    format!("file://{}", path.display())
+}
+
+fn options() -> PartitionChannelOptions {
+    let mut channel_options = ChannelOptions::new();
+    channel_options.set_timeout_ms(1000);
+    let mut options = PartitionChannelOptions::new(&channel_options);
+    options.set_call_mapper(Shard::default());
+    options.set_response_merger(Join);
+    options
+}
+
+fn messages(response: echo::EchoResponse) -> Vec<String> {
+    let mut messages: Vec<_> = response.message.split(',').map(str::to_owned).collect();
+    messages.sort();
+    messages
+}
+
+#[test]
+fn fan_out_to_partitions() {
+    let servers: Vec<_> = (0..2).map(serve).collect();
+    let url = naming_service_url(&servers);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+
+    let pchan =
+        PartitionChannel::with_options(servers.len(), Fraction, &url, "rr", &options()).unwrap();
+    assert_eq!(servers.len(), pchan.partition_count());
+    let client = echo::EchoServiceStub::with_channel(&pchan);
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!(vec!["hello#0@0", "hello#1@1"], messages(response));
+
+    let dchan = DynamicPartitionChannel::with_options(Fraction, &url, "rr", &options()).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&dchan);
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!(vec!["hello#0@0", "hello#1@1"], messages(response));
+}
@@ -0,0 +1,198 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, Controller, RetryPolicy, RpcResult, RpcStatus, Server,
+    ServerContext, ServerOptions,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+use common::block_on;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+/// Retries overloaded and stopping servers, never application errors, and
+/// waits twice as long before each retry, up to `max_backoff`. The waits are
+/// recorded in `backoffs`.
+struct OverloadRetryPolicy {
+    base_backoff: Duration,
+    max_backoff: Duration,
+    backoffs: Mutex<Vec<Duration>>,
+}
+
+impl RetryPolicy for OverloadRetryPolicy {
+    fn do_retry(&self, cntl: &Controller) -> bool {
+        matches!(
+            cntl.status().error(),
+            BrpcError::EOVERCROWDED | BrpcError::ELOGOFF
+        )
+    }
+
+    fn backoff(&self, cntl: &Controller) -> Duration {
+        let factor = 2u32
+            .checked_pow(cntl.retried_count() as u32)
+            .unwrap_or(u32::MAX);
+        let backoff = self
+            .base_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        self.backoffs.lock().unwrap().push(backoff);
+        backoff
+    }
+}
+
+struct NeverRetry;
+
+impl RetryPolicy for NeverRetry {
+    fn do_retry(&self, _cntl: &Controller) -> bool {
+        false
+    }
+}
+
+struct Attempts {
+    failures: Mutex<Vec<i32>>,
+    calls: AtomicUsize,
+}
+
+/// Fails the first calls with the queued error codes, then echoes.
+struct FlakyHandler(Arc<Attempts>);
+
+impl echo::EchoServiceHandler for FlakyHandler {
+    fn echo(
+        &self,
+        _ctx: &mut ServerContext,
+        request: &echo::EchoRequest,
+    ) -> RpcResult<echo::EchoResponse> {
+        self.0.calls.fetch_add(1, Ordering::SeqCst);
+        let mut failures = self.0.failures.lock().unwrap();
+        if !failures.is_empty() {
+            return Err(RpcStatus::new(failures.remove(0), "try again"));
+        }
+        Ok(echo::EchoResponse {
+            message: request.message.clone(),
+        })
+    }
+}
+
+fn start_server(failures: Vec<i32>) -> (Server, Arc<Attempts>) {
+    let attempts = Arc::new(Attempts {
+        failures: Mutex::new(failures),
+        calls: AtomicUsize::new(0),
+    });
+    let mut server = Server::new();
+    server
+        .add_service(echo::EchoService::from_handler(FlakyHandler(
+            attempts.clone(),
+        )))
+        .expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    (server, attempts)
+}
+
+fn channel(port: u16) -> (Channel, Arc<OverloadRetryPolicy>) {
+    let policy = Arc::new(OverloadRetryPolicy {
+        base_backoff: Duration::from_millis(20),
+        max_backoff: Duration::from_millis(100),
+        backoffs: Mutex::new(Vec::new()),
+    });
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_max_retry(3);
+    options.set_retry_policy(policy.clone());
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    (ch, policy)
+}
+
+fn request() -> echo::EchoRequest {
+    echo::EchoRequest {
+        message: "hello".to_owned(),
+    }
+}
+
+#[test]
+fn retry_overloaded_server_with_backoff() {
+    let failures = vec![BrpcError::EOVERCROWDED as i32, BrpcError::ELOGOFF as i32];
+    let (server, attempts) = start_server(failures);
+    let port = server.listen_port();
+    let (ch, policy) = channel(port);
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let mut cntl = Controller::new();
+    let response = client
+        .echo_with(&mut cntl, &request())
+        .expect("echo failed");
+    assert_eq!("hello", response.message);
+    assert_eq!(2, cntl.retried_count());
+    assert_eq!(3, attempts.calls.load(Ordering::SeqCst));
+    // Backed off before each retry, counting the retries of earlier attempts
+    assert_eq!(
+        vec![Duration::from_millis(20), Duration::from_millis(40)],
+        *policy.backoffs.lock().unwrap()
+    );
+}
+
+#[test]
+fn retry_async_call_with_backoff() {
+    let failures = vec![
+        BrpcError::EOVERCROWDED as i32,
+        BrpcError::EOVERCROWDED as i32,
+    ];
+    let (server, attempts) = start_server(failures);
+    let port = server.listen_port();
+    let (ch, _) = channel(port);
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let response = block_on(client.echo_async(&request())).expect("echo failed");
+    assert_eq!("hello", response.message);
+    assert_eq!(3, attempts.calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn application_errors_are_not_retried() {
+    let (server, attempts) = start_server(vec![10001]);
+    let port = server.listen_port();
+    let (ch, _) = channel(port);
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let status = client.echo(&request()).unwrap_err();
+    assert_eq!(10001, status.code);
+    assert_eq!(0, status.retried_count());
+    assert_eq!(1, attempts.calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn call_overrides_channel_policy() {
+    let (server, attempts) = start_server(vec![BrpcError::EOVERCROWDED as i32]);
+    let port = server.listen_port();
+    let (ch, _) = channel(port);
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let mut cntl = Controller::new();
+    cntl.set_retry_policy(NeverRetry);
+    let status = client.echo_with(&mut cntl, &request()).unwrap_err();
+    assert_eq!(BrpcError::EOVERCROWDED, status.error());
+    assert_eq!(1, attempts.calls.load(Ordering::SeqCst));
+
+    // The channel policy applies to the next call
+    let response = client.echo(&request()).expect("echo failed");
+    assert_eq!("hello", response.message);
+}
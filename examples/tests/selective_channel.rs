@@ -0,0 +1,79 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, Protocol, SelectiveChannel, Server};
+use std::collections::HashSet;
+
+mod common;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// An echo server which responds with its `index`
+fn serve(index: usize) -> Server {
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, _request, response| {
+        response.message = index.to_string();
+        Ok(())
+    });
+    common::serve(service)
+}
+
+fn sub_channel(port: u16, protocol: Protocol) -> Channel {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_max_retry(0);
+    options.set_protocol(protocol);
+    Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap()
+}
+
+// Indexes of the servers which responded to a few calls
+fn call(schan: &SelectiveChannel) -> HashSet<usize> {
+    let client = echo::EchoServiceStub::with_channel(schan);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    (0..4)
+        .map(|_| {
+            let response = client.echo(&request).expect("echo failed");
+            response.message.parse().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn balance_across_sub_channels() {
+    let servers: Vec<_> = (0..2).map(serve).collect();
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_max_retry(1);
+    let mut schan = SelectiveChannel::with_options("rr", &options).unwrap();
+    let first = schan
+        .add_channel(sub_channel(servers[0].listen_port(), Protocol::BaiduStd))
+        .expect("Failed to add channel");
+    schan
+        .add_channel(sub_channel(servers[1].listen_port(), Protocol::Http))
+        .expect("Failed to add channel");
+    assert_eq!(vec![0, 1].into_iter().collect::<HashSet<_>>(), call(&schan));
+
+    // Calls are retried on another sub-channel
+    schan
+        .add_channel(sub_channel(common::unused_port(), Protocol::BaiduStd))
+        .expect("Failed to add channel");
+    assert_eq!(vec![0, 1].into_iter().collect::<HashSet<_>>(), call(&schan));
+
+    schan.remove_channel(first).unwrap();
+    assert_eq!(vec![1].into_iter().collect::<HashSet<_>>(), call(&schan));
+}
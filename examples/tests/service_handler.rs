@@ -0,0 +1,150 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, RpcResult, RpcStatus, Server, ServerContext, ServerOptions,
+};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+struct EchoHandler;
+
+impl echo::EchoServiceHandler for EchoHandler {
+    fn echo(
+        &self,
+        _ctx: &mut ServerContext,
+        request: &echo::EchoRequest,
+    ) -> RpcResult<echo::EchoResponse> {
+        if request.message.is_empty() {
+            return Err(RpcStatus::new(10001, "empty message"));
+        }
+        Ok(echo::EchoResponse {
+            message: request.message.clone(),
+        })
+    }
+}
+
+#[test]
+fn serve_with_handler() {
+    let service = echo::EchoService::from_handler(EchoHandler);
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!(request.message, response.message);
+
+    let request = echo::EchoRequest {
+        message: String::new(),
+    };
+    let status = client.echo(&request).unwrap_err();
+    assert_eq!(10001, status.code);
+    assert!(status.message.contains("empty message"));
+}
+
+#[test]
+fn handlers_are_owned_by_service() {
+    let token = Arc::new(());
+    let mut service = echo::EchoService::new();
+    let captured = token.clone();
+    service.set_echo_handler(move |_ctx, request, response| {
+        let _ = &captured;
+        response.message = request.message.clone();
+        Ok(())
+    });
+    assert_eq!(2, Arc::strong_count(&token));
+
+    // Replacing a handler frees the previous one
+    let captured = token.clone();
+    service.set_echo_async_handler(move |_request, responder| {
+        let _ = &captured;
+        responder.respond(Err(BrpcError::EINTERNAL.into()));
+    });
+    assert_eq!(2, Arc::strong_count(&token));
+
+    drop(service);
+    assert_eq!(1, Arc::strong_count(&token));
+}
+
+struct ContextHandler {
+    // Known once the server is started
+    port: Arc<AtomicU16>,
+}
+
+impl echo::EchoServiceHandler for ContextHandler {
+    fn echo(
+        &self,
+        ctx: &mut ServerContext,
+        request: &echo::EchoRequest,
+    ) -> RpcResult<echo::EchoResponse> {
+        let (remote_side, local_side) = match (ctx.remote_side(), ctx.local_side()) {
+            (Some(remote_side), Some(local_side)) => (remote_side, local_side),
+            _ => return Err(BrpcError::EREQUEST.into()),
+        };
+        if !remote_side.ip().is_loopback() || local_side.port() != self.port.load(Ordering::SeqCst)
+        {
+            return Err(BrpcError::EREQUEST.into());
+        }
+        if ctx.header("x-tenant").is_some() || !ctx.request_attachment().is_empty() {
+            return Err(BrpcError::EREQUEST.into());
+        }
+        if let Some(remaining) = ctx.remaining_deadline() {
+            if remaining > Duration::from_millis(1000) {
+                return Err(BrpcError::EREQUEST.into());
+            }
+        }
+        ctx.set_response_attachment(b"attachment");
+        Ok(echo::EchoResponse {
+            message: format!("{} from {}", request.message, remote_side.ip()),
+        })
+    }
+}
+
+#[test]
+fn handler_sees_context() {
+    let port = Arc::new(AtomicU16::new(0));
+    let service = echo::EchoService::from_handler(ContextHandler { port: port.clone() });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    port.store(server.listen_port(), Ordering::SeqCst);
+
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let ch = Channel::new(&format!("127.0.0.1:{}", server.listen_port()), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!("hello from 127.0.0.1", response.message);
+}
@@ -0,0 +1,93 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, Service};
+use std::sync::Arc;
+
+mod common;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// An echo service whose handler holds a clone of `token`
+fn echo_service(token: &Arc<()>) -> echo::EchoService {
+    let token = token.clone();
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, request, response| {
+        let _ = &token;
+        response.message = request.message.clone();
+        Ok(())
+    });
+    service
+}
+
+fn call(port: u16) {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    let response = client.echo(&request).expect("echo failed");
+    assert_eq!(request.message, response.message);
+}
+
+#[test]
+fn server_owns_service() {
+    let token = Arc::new(());
+    let server = common::serve(echo_service(&token));
+    call(server.listen_port());
+    assert_eq!(2, Arc::strong_count(&token));
+    drop(server);
+    assert_eq!(1, Arc::strong_count(&token));
+}
+
+#[test]
+fn server_owns_boxed_service() {
+    let token = Arc::new(());
+    let service: Box<dyn Service> = Box::new(echo_service(&token));
+    let server = common::serve(service);
+    call(server.listen_port());
+    drop(server);
+    assert_eq!(1, Arc::strong_count(&token));
+}
+
+#[test]
+fn server_shares_service() {
+    let token = Arc::new(());
+    let service = Arc::new(echo_service(&token));
+    let server = common::serve(service.clone());
+    call(server.listen_port());
+
+    // The service outlives the server as long as it is shared
+    drop(server);
+    assert_eq!(2, Arc::strong_count(&token));
+    drop(service);
+    assert_eq!(1, Arc::strong_count(&token));
+}
+
+#[test]
+fn shared_service_outlives_its_last_handle() {
+    let token = Arc::new(());
+    let service = Arc::new(echo_service(&token));
+    let server = common::serve(service.clone());
+
+    // Dropping the caller's handle keeps the service alive in the server
+    drop(service);
+    call(server.listen_port());
+    drop(server);
+    assert_eq!(1, Arc::strong_count(&token));
+}
@@ -0,0 +1,61 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, Server, ServerOptions};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+#[test]
+fn shutdown_drains_in_flight_requests() {
+    let (started_tx, started_rx) = mpsc::sync_channel(1);
+    let mut service = echo::EchoService::new();
+    service.set_echo_handler(move |_ctx, request, response| {
+        let _ = started_tx.try_send(());
+        thread::sleep(Duration::from_millis(200));
+        response.message = request.message.clone();
+        Ok(())
+    });
+    let mut server = Server::new();
+    server.add_service(service).expect("Failed to add service");
+    server
+        .start(0, &ServerOptions::new())
+        .expect("Failed to start service");
+    let port = server.listen_port();
+
+    let client = thread::spawn(move || {
+        let mut options = ChannelOptions::new();
+        options.set_timeout_ms(1000);
+        let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+        let client = echo::EchoServiceStub::with_channel(&ch);
+        let request = echo::EchoRequest {
+            message: "hello".to_owned(),
+        };
+        client.echo(&request)
+    });
+
+    // Shut down while the request is being served
+    started_rx.recv().unwrap();
+    let handle = server.shutdown_handle();
+    thread::spawn(move || handle.shutdown());
+    server.run();
+    assert!(!server.is_running());
+
+    let response = client.join().unwrap().expect("echo failed");
+    assert_eq!("hello", response.message);
+}
@@ -0,0 +1,116 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{Channel, ChannelOptions, ChannelSslOptions};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+mod common;
+
+use common::Cert;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+// echo/cpp_peer.cpp
+extern "C" {
+    fn cpp_echo_server_start_ssl(
+        port: c_int,
+        certificate: *const c_char,
+        private_key: *const c_char,
+        client_ca_file: *const c_char,
+    ) -> *mut c_void;
+    fn cpp_echo_server_port(server: *mut c_void) -> c_int;
+    fn cpp_echo_server_stop(server: *mut c_void);
+}
+
+fn start_server(cert: &Cert, client_ca: Option<&Cert>) -> *mut c_void {
+    let certificate = CString::new(cert.certificate.as_str()).unwrap();
+    let private_key = CString::new(cert.private_key.as_str()).unwrap();
+    let client_ca_file = client_ca.map(|ca| CString::new(ca.ca_file()).unwrap());
+    let server = unsafe {
+        cpp_echo_server_start_ssl(
+            0,
+            certificate.as_ptr(),
+            private_key.as_ptr(),
+            client_ca_file.as_ref().map_or(ptr::null(), |f| f.as_ptr()),
+        )
+    };
+    assert!(!server.is_null());
+    server
+}
+
+fn echo(port: c_int, ssl_options: &ChannelSslOptions) -> bool {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_ssl_options(ssl_options);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    match client.echo(&request) {
+        Ok(response) => {
+            assert_eq!("hello", response.message);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn verify_server_certificate() {
+    let server_cert = Cert::generate("echo.test");
+    let other_cert = Cert::generate("other.test");
+    let server = start_server(&server_cert, None);
+    let port = unsafe { cpp_echo_server_port(server) };
+
+    let mut ssl = ChannelSslOptions::new();
+    ssl.set_verify_depth(1);
+    ssl.set_ca_file(server_cert.ca_file()).unwrap();
+    ssl.set_sni_name("echo.test").unwrap();
+    assert!(echo(port, &ssl));
+
+    // The server certificate is not signed by this CA
+    let mut ssl = ChannelSslOptions::new();
+    ssl.set_verify_depth(1);
+    ssl.set_ca_file(other_cert.ca_file()).unwrap();
+    assert!(!echo(port, &ssl));
+
+    // Not verifying at all, which is the default
+    assert!(echo(port, &ChannelSslOptions::new()));
+
+    unsafe { cpp_echo_server_stop(server) };
+}
+
+#[test]
+fn mutual_tls() {
+    let server_cert = Cert::generate("mtls.test");
+    let client_cert = Cert::generate("client.test");
+    let server = start_server(&server_cert, Some(&client_cert));
+    let port = unsafe { cpp_echo_server_port(server) };
+
+    let mut ssl = ChannelSslOptions::new();
+    ssl.set_verify_depth(1);
+    ssl.set_ca_file(server_cert.ca_file()).unwrap();
+    assert!(!echo(port, &ssl));
+
+    ssl.set_client_certificate(&client_cert.certificate, &client_cert.private_key)
+        .unwrap();
+    assert!(echo(port, &ssl));
+
+    unsafe { cpp_echo_server_stop(server) };
+}
@@ -0,0 +1,154 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_rs::{
+    BrpcError, Channel, ChannelOptions, ChannelSslOptions, RpcResult, Server, ServerContext,
+    ServerOptions, ServerSslOptions,
+};
+
+mod common;
+
+use common::Cert;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+    include!(concat!(env!("OUT_DIR"), "/example.brpc.rs"));
+}
+
+struct SubjectHandler;
+
+impl echo::EchoServiceHandler for SubjectHandler {
+    fn echo(
+        &self,
+        ctx: &mut ServerContext,
+        _request: &echo::EchoRequest,
+    ) -> RpcResult<echo::EchoResponse> {
+        let subject = ctx.peer_certificate_subject();
+        Ok(echo::EchoResponse {
+            message: subject.ok_or(BrpcError::EREQUEST)?,
+        })
+    }
+}
+
+fn start_server(ssl_options: &ServerSslOptions) -> Server {
+    let mut server = Server::new();
+    server
+        .add_service(echo::EchoService::from_handler(SubjectHandler))
+        .expect("Failed to add service");
+    let mut options = ServerOptions::new();
+    options.set_ssl_options(ssl_options);
+    server.start(0, &options).expect("Failed to start service");
+    server
+}
+
+/// Subject of the client certificate seen by the server
+fn echo(port: u16, ssl_options: &ChannelSslOptions) -> RpcResult<String> {
+    let mut options = ChannelOptions::new();
+    options.set_timeout_ms(1000);
+    options.set_ssl_options(ssl_options);
+    let ch = Channel::new(&format!("127.0.0.1:{}", port), &options).unwrap();
+    let client = echo::EchoServiceStub::with_channel(&ch);
+    let request = echo::EchoRequest {
+        message: "hello".to_owned(),
+    };
+    client.echo(&request).map(|response| response.message)
+}
+
+fn verifying(ca: &Cert, sni_name: Option<&str>) -> ChannelSslOptions {
+    let mut ssl = ChannelSslOptions::new();
+    ssl.set_verify_depth(1);
+    ssl.set_ca_file(ca.ca_file()).unwrap();
+    if let Some(name) = sni_name {
+        ssl.set_sni_name(name).unwrap();
+    }
+    ssl
+}
+
+#[test]
+fn sni_certificates() {
+    let default_cert = Cert::generate("default.test");
+    let sni_cert = Cert::generate("sni.test");
+    let client_cert = Cert::generate("client.test");
+    let mut ssl = ServerSslOptions::new();
+    ssl.set_default_certificate(&default_cert.certificate, &default_cert.private_key)
+        .unwrap();
+    ssl.add_sni_certificate(&sni_cert.certificate, &sni_cert.private_key, &["sni.test"])
+        .unwrap();
+    ssl.require_client_certificate(client_cert.ca_file(), 1)
+        .unwrap();
+    let server = start_server(&ssl);
+    let port = server.listen_port();
+
+    let with_client_cert = |mut ssl: ChannelSslOptions| {
+        ssl.set_client_certificate(&client_cert.certificate, &client_cert.private_key)
+            .unwrap();
+        ssl
+    };
+    let subject = Some("CN=client.test".to_owned());
+    let ssl = with_client_cert(verifying(&sni_cert, Some("sni.test")));
+    assert_eq!(subject, echo(port, &ssl).ok());
+    let ssl = with_client_cert(verifying(&default_cert, None));
+    assert_eq!(subject, echo(port, &ssl).ok());
+    let ssl = with_client_cert(verifying(&default_cert, Some("unknown.test")));
+    assert_eq!(subject, echo(port, &ssl).ok());
+    // The server presents the SNI certificate, not the default one
+    let ssl = with_client_cert(verifying(&default_cert, Some("sni.test")));
+    assert_eq!(None, echo(port, &ssl).ok());
+}
+
+#[test]
+fn client_certificate_required() {
+    let server_cert = Cert::generate("server.test");
+    let client_cert = Cert::generate("trusted.test");
+    let other_cert = Cert::generate("untrusted.test");
+    let mut ssl = ServerSslOptions::new();
+    ssl.set_default_certificate(&server_cert.certificate, &server_cert.private_key)
+        .unwrap();
+    ssl.require_client_certificate(client_cert.ca_file(), 1)
+        .unwrap();
+    let server = start_server(&ssl);
+    let port = server.listen_port();
+
+    assert_eq!(None, echo(port, &verifying(&server_cert, None)).ok());
+
+    let mut ssl = verifying(&server_cert, None);
+    ssl.set_client_certificate(&other_cert.certificate, &other_cert.private_key)
+        .unwrap();
+    assert_eq!(None, echo(port, &ssl).ok());
+
+    let mut ssl = verifying(&server_cert, None);
+    ssl.set_client_certificate(&client_cert.certificate, &client_cert.private_key)
+        .unwrap();
+    assert_eq!(Some("CN=trusted.test".to_owned()), echo(port, &ssl).ok());
+}
+
+#[test]
+fn client_certificate_optional() {
+    let server_cert = Cert::generate("optional.test");
+    let mut ssl = ServerSslOptions::new();
+    ssl.set_default_certificate(&server_cert.certificate, &server_cert.private_key)
+        .unwrap();
+    let server = start_server(&ssl);
+    let port = server.listen_port();
+
+    // Without a client certificate, the handler has no subject to respond
+    let status = echo(port, &verifying(&server_cert, None)).unwrap_err();
+    assert_eq!(BrpcError::EREQUEST, status.error());
+
+    let mut ssl = ServerSslOptions::new();
+    ssl.set_default_certificate("/nonexistent/cert.pem", "/nonexistent/key.pem")
+        .unwrap();
+    let mut options = ServerOptions::new();
+    options.set_ssl_options(&ssl);
+    assert!(Server::new().start(0, &options).is_err());
+}
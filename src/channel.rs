@@ -11,28 +11,145 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use brpc_sys::ffi::{self, BrpcChannel, BrpcChannelOptions};
-use libc::c_int;
-use std::ffi::CString;
-use std::net::SocketAddr;
+use crate::controller;
+use crate::retry::{RetryPolicy, RetryPolicyHandle};
+use crate::unwind::catch_panic;
+use crate::{BrpcError, BrpcResult, RpcResult};
+use brpc_sys::ffi::{
+    self, BrpcChannel, BrpcChannelOptions, BrpcChannelSslOptions, BrpcController,
+    BrpcDynamicPartitionChannel, BrpcIOBuf, BrpcParallelChannel, BrpcParallelChannelOptions,
+    BrpcPartitionChannel, BrpcPartitionChannelOptions, BrpcRpcChannel, BrpcSelectiveChannel,
+};
+use libc::{c_char, c_int, c_void};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::ptr;
+use std::sync::Arc;
+
+/// Address of a BRPC server, see `Channel::new()`. Besides a `SocketAddrV4`,
+/// it may be a string such as `"127.0.0.1:8000"` or a host name and port such
+/// as `"svc.local:8000"`. Endpoints of BRPC 0.9.6 are IPv4 only, so IPv6
+/// addresses and Unix domain sockets are not supported.
+pub trait ToEndpoint {
+    #[doc(hidden)]
+    fn to_endpoint(&self) -> String;
+}
+
+impl ToEndpoint for SocketAddrV4 {
+    fn to_endpoint(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToEndpoint for str {
+    fn to_endpoint(&self) -> String {
+        self.to_owned()
+    }
+}
+
+impl ToEndpoint for String {
+    fn to_endpoint(&self) -> String {
+        self.clone()
+    }
+}
+
+/// Implemented by every kind of channel. Client stubs are created with
+/// `with_channel()` over any `ChannelBase`.
+///
+/// # Safety
+///
+/// `rpc_channel()` must return a valid `google::protobuf::RpcChannel` which
+/// lives as long as `self`.
+pub unsafe trait ChannelBase {
+    #[doc(hidden)]
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel;
+}
 
 /// A `Channel` provides a connection to a BRPC server on a specified host and
 /// port and is used when creating a client stub
 pub struct Channel {
     pub inner: *mut BrpcChannel, // brpc_channel_t in ffi.cpp
+    _retry_policy: Option<Arc<RetryPolicyHandle>>,
 }
 
 impl Channel {
+    /// Make a `Channel` to the BRPC server at `endpoint` with the provided
+    /// `ChannelOptions`. Fails with `BrpcError::ECHANNEL` if e.g. the endpoint
+    /// is invalid or its host name cannot be resolved, and right away for
+    /// IPv6 addresses and Unix domain sockets given as strings.
+    pub fn new<E: ToEndpoint + ?Sized>(endpoint: &E, options: &ChannelOptions) -> BrpcResult<Self> {
+        let endpoint = endpoint.to_endpoint();
+        // Not parsed by butil of BRPC 0.9.6, which would fail later or resolve
+        // them as host names
+        if endpoint.starts_with('[') || endpoint.starts_with("unix:") {
+            return Err(BrpcError::ECHANNEL);
+        }
+        let endpoint = CString::new(endpoint).map_err(|_| BrpcError::ECHANNEL)?;
+        let channel = Channel {
+            inner: unsafe { ffi::brpc_channel_new() },
+            _retry_policy: options.retry_policy.clone(),
+        };
+        if 0 == unsafe { ffi::brpc_channel_init(channel.inner, endpoint.as_ptr(), options.inner) } {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+
+    /// Make a `Channel` to a cluster of BRPC servers. Servers are discovered
+    /// by the naming service at `naming_service_url` (e.g.
+    /// `"list://127.0.0.1:50000,127.0.0.1:50001"` or `"file://servers.conf"`)
+    /// and requests are distributed among them by the load balancer named
+    /// `load_balancer_name` (e.g. `"rr"`, `"wrr"`, `"random"`, `"la"` or
+    /// `"c_murmurhash"`). Fails with `BrpcError::ECHANNEL` if e.g. the naming
+    /// service or the load balancer is unknown.
+    pub fn new_with_naming_service(
+        naming_service_url: &str,
+        load_balancer_name: &str,
+        options: &ChannelOptions,
+    ) -> BrpcResult<Self> {
+        let naming_service_url =
+            CString::new(naming_service_url).map_err(|_| BrpcError::ECHANNEL)?;
+        let load_balancer_name =
+            CString::new(load_balancer_name).map_err(|_| BrpcError::ECHANNEL)?;
+        let channel = Channel {
+            inner: unsafe { ffi::brpc_channel_new() },
+            _retry_policy: options.retry_policy.clone(),
+        };
+        let ret = unsafe {
+            ffi::brpc_channel_init_with_naming_service(
+                channel.inner,
+                naming_service_url.as_ptr(),
+                load_balancer_name.as_ptr(),
+                options.inner,
+            )
+        };
+        if ret == 0 {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+
     /// Make a `Channel` with the provided socker address and `ChannelOptions`.
+    /// Panics if the channel cannot be initialized, see `new()`.
+    #[deprecated(note = "panics on failure, use `Channel::new()` instead")]
     pub fn with_options(sockaddr: &SocketAddr, options: &ChannelOptions) -> Self {
-        let inner = unsafe { ffi::brpc_channel_new() };
-        // safe to unwrap() because format!(sockaddr) does NOT contains \0
-        let server_addr_and_port = CString::new(format!("{}", sockaddr)).unwrap();
-        let server_addr_and_port_ptr = server_addr_and_port.as_c_str().as_ptr();
-        assert!(
-            0 == unsafe { ffi::brpc_channel_init(inner, server_addr_and_port_ptr, options.inner) }
-        );
-        Channel { inner }
+        Self::new(&sockaddr.to_string(), options).expect("Failed to initialize channel")
+    }
+
+    /// Make a `Channel` to a cluster of BRPC servers. Panics if the channel
+    /// cannot be initialized, see `new_with_naming_service()`.
+    #[deprecated(note = "panics on failure, use `Channel::new_with_naming_service()` instead")]
+    pub fn with_naming_service(
+        naming_service_url: &str,
+        load_balancer_name: &str,
+        options: &ChannelOptions,
+    ) -> Self {
+        Self::new_with_naming_service(naming_service_url, load_balancer_name, options)
+            .expect("Failed to initialize channel")
     }
 }
 
@@ -44,10 +161,72 @@ impl Drop for Channel {
     }
 }
 
+unsafe impl ChannelBase for Channel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        unsafe { ffi::brpc_channel_as_rpc_channel(self.inner) }
+    }
+}
+
+/// Wire protocol used by a `Channel` to talk to BRPC servers. A BRPC server
+/// accepts all of these protocols on the same port.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// `baidu_std`, the standard binary protocol of BRPC
+    BaiduStd,
+    /// `http`, HTTP/1.x
+    Http,
+    /// `h2`, HTTP/2
+    H2,
+    /// `h2:grpc`, gRPC over HTTP/2
+    Grpc,
+    /// `hulu_pbrpc`
+    Hulu,
+    /// `sofa_pbrpc`
+    Sofa,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::BaiduStd => "baidu_std",
+            Protocol::Http => "http",
+            Protocol::H2 => "h2",
+            Protocol::Grpc => "h2:grpc",
+            Protocol::Hulu => "hulu_pbrpc",
+            Protocol::Sofa => "sofa_pbrpc",
+        }
+    }
+}
+
+/// How a `Channel` connects to a BRPC server
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionType {
+    /// One connection to each server, shared by all channels and calls. This
+    /// is the default of most protocols.
+    Single,
+    /// A pool of connections to each server, each used by one call at a time.
+    /// BRPC 0.9.6 cannot size the pool per channel: its size is the
+    /// process-wide gflag `max_connection_pool_size`.
+    Pooled,
+    /// A new connection for each call, closed once the call finishes.
+    Short,
+}
+
+impl ConnectionType {
+    fn name(self) -> &'static str {
+        match self {
+            ConnectionType::Single => "single",
+            ConnectionType::Pooled => "pooled",
+            ConnectionType::Short => "short",
+        }
+    }
+}
+
 /// Options for a `Channel`
 pub struct ChannelOptions {
     #[doc(hidden)]
     pub inner: *mut BrpcChannelOptions,
+    retry_policy: Option<Arc<RetryPolicyHandle>>,
 }
 
 impl Default for ChannelOptions {
@@ -61,6 +240,7 @@ impl ChannelOptions {
     pub fn new() -> Self {
         ChannelOptions {
             inner: unsafe { ffi::brpc_channel_options_new() },
+            retry_policy: None,
         }
     }
 
@@ -74,6 +254,63 @@ impl ChannelOptions {
     pub fn set_max_retry(&mut self, timeout: i32) {
         unsafe { ffi::brpc_channel_options_set_max_retry(self.inner, timeout as c_int) }
     }
+
+    /// Decide whether failed RPCs over this channel are retried with `policy`
+    /// instead of the default policy of BRPC. The policy is shared by all
+    /// channels made with these options.
+    pub fn set_retry_policy<P: RetryPolicy + 'static>(&mut self, policy: P) {
+        let policy = RetryPolicyHandle::new(policy);
+        unsafe { ffi::brpc_channel_options_set_retry_policy(self.inner, policy.inner) };
+        self.retry_policy = Some(policy);
+    }
+
+    /// Set the wire protocol of this channel. The default protocol is
+    /// `Protocol::BaiduStd`.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        // safe to unwrap() because protocol names do NOT contain \0
+        let name = CString::new(protocol.name()).unwrap();
+        unsafe { ffi::brpc_channel_options_set_protocol(self.inner, name.as_ptr()) }
+    }
+
+    /// Set how this channel connects to servers. The default depends on the
+    /// protocol, `ConnectionType::Single` for `Protocol::BaiduStd`.
+    pub fn set_connection_type(&mut self, connection_type: ConnectionType) {
+        // safe to unwrap() because connection type names do NOT contain \0
+        let name = CString::new(connection_type.name()).unwrap();
+        unsafe { ffi::brpc_channel_options_set_connection_type(self.inner, name.as_ptr()) }
+    }
+
+    /// Set max duration of connecting to a server in milliseconds. -1 means
+    /// wait until the RPC times out.
+    pub fn set_connect_timeout_ms(&mut self, timeout: i32) {
+        unsafe { ffi::brpc_channel_options_set_connect_timeout_ms(self.inner, timeout as c_int) }
+    }
+
+    /// Set the connection group of this channel. Channels in different groups
+    /// never share connections, even with `ConnectionType::Single`, so a group
+    /// gets connections of its own. Channels are in the same, unnamed group by
+    /// default. Fails with `BrpcError::EARGUMENT` if `connection_group`
+    /// contains a NUL byte.
+    pub fn set_connection_group(&mut self, connection_group: &str) -> BrpcResult<()> {
+        let group = CString::new(connection_group).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe { ffi::brpc_channel_options_set_connection_group(self.inner, group.as_ptr()) };
+        Ok(())
+    }
+
+    /// Send a backup request to another server if the first one has not
+    /// responded within `backup_request_ms` milliseconds, and take whichever
+    /// response comes first. -1 means no backup request, which is the default.
+    pub fn set_backup_request_ms(&mut self, backup_request_ms: i32) {
+        unsafe {
+            ffi::brpc_channel_options_set_backup_request_ms(self.inner, backup_request_ms as c_int)
+        }
+    }
+
+    /// Talk to servers over TLS with `ssl_options`. Channels are plaintext by
+    /// default.
+    pub fn set_ssl_options(&mut self, ssl_options: &ChannelSslOptions) {
+        unsafe { ffi::brpc_channel_options_set_ssl_options(self.inner, ssl_options.inner) }
+    }
 }
 
 impl Drop for ChannelOptions {
@@ -84,10 +321,757 @@ impl Drop for ChannelOptions {
     }
 }
 
+/// TLS options of a `Channel`, see `ChannelOptions::set_ssl_options()`.
+/// Setters taking strings fail with `BrpcError::EARGUMENT` if they contain a
+/// NUL byte.
+pub struct ChannelSslOptions {
+    #[doc(hidden)]
+    pub inner: *mut BrpcChannelSslOptions,
+}
+
+impl Default for ChannelSslOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelSslOptions {
+    /// Make a `ChannelSslOptions` with default values, which do NOT verify
+    /// server certificates.
+    pub fn new() -> Self {
+        ChannelSslOptions {
+            inner: unsafe { ffi::brpc_channel_ssl_options_new() },
+        }
+    }
+
+    /// Set the PEM file of trusted CA certificates that server certificates
+    /// are verified against. The system CAs are used if it is not set.
+    pub fn set_ca_file(&mut self, ca_file_path: &str) -> BrpcResult<()> {
+        let path = CString::new(ca_file_path).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe { ffi::brpc_channel_ssl_options_set_ca_file(self.inner, path.as_ptr()) };
+        Ok(())
+    }
+
+    /// Set the max depth of the certificate chain of a server. 0 means server
+    /// certificates are NOT verified, which is the default.
+    pub fn set_verify_depth(&mut self, verify_depth: i32) {
+        unsafe { ffi::brpc_channel_ssl_options_set_verify_depth(self.inner, verify_depth as c_int) }
+    }
+
+    /// Present a client certificate to servers requiring one, i.e. mutual
+    /// TLS. `certificate` and `private_key` are either PEM file paths or PEM
+    /// strings.
+    pub fn set_client_certificate(
+        &mut self,
+        certificate: &str,
+        private_key: &str,
+    ) -> BrpcResult<()> {
+        let certificate = CString::new(certificate).map_err(|_| BrpcError::EARGUMENT)?;
+        let private_key = CString::new(private_key).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe {
+            ffi::brpc_channel_ssl_options_set_client_cert(
+                self.inner,
+                certificate.as_ptr(),
+                private_key.as_ptr(),
+            )
+        };
+        Ok(())
+    }
+
+    /// Set the server name sent in the TLS handshake (SNI), which servers use
+    /// to pick a certificate. None is sent by default.
+    pub fn set_sni_name(&mut self, sni_name: &str) -> BrpcResult<()> {
+        let name = CString::new(sni_name).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe { ffi::brpc_channel_ssl_options_set_sni_name(self.inner, name.as_ptr()) };
+        Ok(())
+    }
+
+    /// Set the allowed cipher suites in OpenSSL cipher list format, such as
+    /// `"ECDHE-RSA-AES256-GCM-SHA384"`.
+    pub fn set_ciphers(&mut self, ciphers: &str) -> BrpcResult<()> {
+        let ciphers = CString::new(ciphers).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe { ffi::brpc_channel_ssl_options_set_ciphers(self.inner, ciphers.as_ptr()) };
+        Ok(())
+    }
+}
+
+impl Drop for ChannelSslOptions {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_channel_ssl_options_destroy(self.inner);
+        }
+    }
+}
+
+/// Decides what a sub-channel of a `ParallelChannel` sends. Requests are
+/// protobuf-encoded, e.g. decode them with `prost::Message::decode()`.
+pub trait CallMapper: Send + Sync + 'static {
+    /// Map the `request` of a call to `method` (e.g.
+    /// `"example.EchoService.echo"`) to the sub-call of the sub-channel at
+    /// `channel_index`.
+    fn map(&self, channel_index: usize, method: &str, request: &[u8]) -> SubCall;
+}
+
+/// Sub-call of a sub-channel of a `ParallelChannel`, see `CallMapper`
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubCall {
+    /// Send the request of the call unchanged
+    Forward,
+    /// Send this protobuf-encoded request instead
+    Request(Vec<u8>),
+    /// Do not call this sub-channel
+    Skip,
+    /// Fail the whole call with `BrpcError::EREQUEST`
+    Bad,
+}
+
+/// Merges the responses of the sub-channels of a `ParallelChannel` into the
+/// response of the call. Responses are protobuf-encoded.
+pub trait ResponseMerger: Send + Sync + 'static {
+    /// Merge `sub_response` into `response`, which holds the responses merged
+    /// so far and is empty at first. Appending to `response` is cheaper than
+    /// rewriting it, as only the appended bytes are parsed back.
+    fn merge(&self, response: &mut Vec<u8>, sub_response: &[u8]) -> MergeResult;
+}
+
+/// Outcome of `ResponseMerger::merge()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeResult {
+    /// The sub-response was merged
+    Merged = 0,
+    /// The sub-response was not merged and counts as a failed sub-call
+    Fail = 1,
+    /// The sub-response was not merged and the call fails right away
+    FailAll = 2,
+}
+
+// Must match the enums in ffi.cpp
+const SUB_CALL_FORWARD: c_int = 0;
+const SUB_CALL_REQUEST: c_int = 1;
+const SUB_CALL_SKIP: c_int = 2;
+const SUB_CALL_BAD: c_int = 3;
+// `MergeResult::Merged` with only the appended bytes handed back
+const MERGE_APPENDED: c_int = 3;
+
+unsafe fn iobuf_to_vec(buf: *const BrpcIOBuf) -> Vec<u8> {
+    let mut data = vec![0; ffi::brpc_iobuf_size(buf)];
+    let len = ffi::brpc_iobuf_copy_to(buf, data.as_mut_ptr() as *mut c_void, data.len());
+    data.truncate(len);
+    data
+}
+
+extern "C" fn map_call(
+    arg: *mut c_void,
+    channel_index: c_int,
+    method: *const c_char,
+    request: *const BrpcIOBuf,
+    sub_request: *mut BrpcIOBuf,
+) -> c_int {
+    let mapper = unsafe { &*(arg as *const Box<dyn CallMapper>) };
+    let method = unsafe { CStr::from_ptr(method) }.to_string_lossy();
+    let request = unsafe { iobuf_to_vec(request) };
+    let sub_call = catch_panic(|| Ok(mapper.map(channel_index as usize, &method, &request)))
+        .unwrap_or(SubCall::Bad);
+    match sub_call {
+        SubCall::Forward => SUB_CALL_FORWARD,
+        SubCall::Request(data) => {
+            unsafe {
+                ffi::brpc_iobuf_assign(sub_request, data.as_ptr() as *const c_void, data.len())
+            };
+            SUB_CALL_REQUEST
+        }
+        SubCall::Skip => SUB_CALL_SKIP,
+        SubCall::Bad => SUB_CALL_BAD,
+    }
+}
+
+extern "C" fn drop_call_mapper(arg: *mut c_void) {
+    drop(unsafe { Box::from_raw(arg as *mut Box<dyn CallMapper>) });
+}
+
+extern "C" fn merge_response(
+    arg: *mut c_void,
+    response: *mut BrpcIOBuf,
+    sub_response: *const BrpcIOBuf,
+) -> c_int {
+    let merger = unsafe { &*(arg as *const Box<dyn ResponseMerger>) };
+    let merged = unsafe { iobuf_to_vec(response) };
+    let mut data = merged.clone();
+    let sub_response = unsafe { iobuf_to_vec(sub_response) };
+    let result =
+        catch_panic(|| Ok(merger.merge(&mut data, &sub_response))).unwrap_or(MergeResult::Fail);
+    if result != MergeResult::Merged {
+        return result as c_int;
+    }
+    // Most mergers append to the response, and then only the appended bytes
+    // are parsed into it rather than the whole response again
+    let (result, data) = if data.starts_with(&merged) {
+        (MERGE_APPENDED, &data[merged.len()..])
+    } else {
+        (result as c_int, &data[..])
+    };
+    unsafe { ffi::brpc_iobuf_assign(response, data.as_ptr() as *const c_void, data.len()) };
+    result
+}
+
+extern "C" fn drop_response_merger(arg: *mut c_void) {
+    drop(unsafe { Box::from_raw(arg as *mut Box<dyn ResponseMerger>) });
+}
+
+/// A `ParallelChannel` sends each call to all of its sub-channels at the same
+/// time and merges their responses into the response of the call.
+pub struct ParallelChannel {
+    inner: *mut BrpcParallelChannel,
+    // Sub-channels are owned here and outlive `inner`
+    channels: Vec<Channel>,
+}
+
+impl ParallelChannel {
+    /// Make an empty `ParallelChannel` with the provided
+    /// `ParallelChannelOptions`. Fails with `BrpcError::ECHANNEL` if it cannot
+    /// be initialized.
+    pub fn with_options(options: &ParallelChannelOptions) -> BrpcResult<Self> {
+        let channel = ParallelChannel {
+            inner: unsafe { ffi::brpc_parallel_channel_new() },
+            channels: Vec::new(),
+        };
+        if 0 == unsafe { ffi::brpc_parallel_channel_init(channel.inner, options.inner) } {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+
+    /// Add a sub-channel. Without a `CallMapper` the sub-channel is sent the
+    /// request of the call unchanged, and without a `ResponseMerger` its
+    /// response is merged with protobuf merge semantics.
+    pub fn add_channel(
+        &mut self,
+        channel: Channel,
+        mapper: Option<Box<dyn CallMapper>>,
+        merger: Option<Box<dyn ResponseMerger>>,
+    ) -> BrpcResult<()> {
+        let (map, map_arg) = match mapper {
+            Some(mapper) => (
+                Some(map_call as ffi::CallMapperFn),
+                Box::into_raw(Box::new(mapper)) as *mut c_void,
+            ),
+            None => (None, ptr::null_mut()),
+        };
+        let (merge, merge_arg) = match merger {
+            Some(merger) => (
+                Some(merge_response as ffi::ResponseMergerFn),
+                Box::into_raw(Box::new(merger)) as *mut c_void,
+            ),
+            None => (None, ptr::null_mut()),
+        };
+        // The mapper and the merger are dropped by BRPC, even on failure
+        let ret = unsafe {
+            ffi::brpc_parallel_channel_add_channel(
+                self.inner,
+                channel.inner,
+                map,
+                map_arg,
+                drop_call_mapper,
+                merge,
+                merge_arg,
+                drop_response_merger,
+            )
+        };
+        if ret == 0 {
+            self.channels.push(channel);
+            Ok(())
+        } else {
+            Err(BrpcError::EINTERNAL)
+        }
+    }
+
+    /// Number of sub-channels.
+    pub fn channel_count(&self) -> usize {
+        unsafe { ffi::brpc_parallel_channel_channel_count(self.inner) as usize }
+    }
+}
+
+impl Drop for ParallelChannel {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_parallel_channel_destroy(self.inner);
+        }
+    }
+}
+
+unsafe impl ChannelBase for ParallelChannel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        unsafe { ffi::brpc_parallel_channel_as_rpc_channel(self.inner) }
+    }
+}
+
+/// Options for a `ParallelChannel`
+pub struct ParallelChannelOptions {
+    #[doc(hidden)]
+    pub inner: *mut BrpcParallelChannelOptions,
+}
+
+impl Default for ParallelChannelOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParallelChannelOptions {
+    /// Make a `ParallelChannelOptions` with default values.
+    pub fn new() -> Self {
+        ParallelChannelOptions {
+            inner: unsafe { ffi::brpc_parallel_channel_options_new() },
+        }
+    }
+
+    /// Set max duration of RPC in milliseconds over this channel, covering all
+    /// sub-calls. -1 means wait indefinitely.
+    pub fn set_timeout_ms(&mut self, timeout: i32) {
+        unsafe { ffi::brpc_parallel_channel_options_set_timeout_ms(self.inner, timeout as c_int) }
+    }
+
+    /// Fail a call with `BrpcError::ETOOMANYFAILS` as soon as `fail_limit`
+    /// sub-calls have failed. <=0 means the call fails only if all sub-calls
+    /// fail, which is the default.
+    pub fn set_fail_limit(&mut self, fail_limit: i32) {
+        unsafe {
+            ffi::brpc_parallel_channel_options_set_fail_limit(self.inner, fail_limit as c_int)
+        }
+    }
+
+    /// Finish a call as soon as `success_limit` sub-calls have succeeded,
+    /// without waiting for the others. <=0 means wait for all sub-calls, which
+    /// is the default.
+    pub fn set_success_limit(&mut self, success_limit: i32) {
+        unsafe {
+            ffi::brpc_parallel_channel_options_set_success_limit(self.inner, success_limit as c_int)
+        }
+    }
+}
+
+impl Drop for ParallelChannelOptions {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_parallel_channel_options_destroy(self.inner);
+        }
+    }
+}
+
+/// A `SelectiveChannel` balances calls across sub-channels, which may differ
+/// in e.g. protocol or naming service. A failed call is retried on another
+/// sub-channel.
+pub struct SelectiveChannel {
+    inner: *mut BrpcSelectiveChannel,
+    _retry_policy: Option<Arc<RetryPolicyHandle>>,
+    // Sub-channels, which are owned by `inner`, with their retry policies
+    channels: HashMap<ChannelHandle, Option<Arc<RetryPolicyHandle>>>,
+}
+
+/// Identifies a sub-channel of a `SelectiveChannel`, see
+/// `SelectiveChannel::remove_channel()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelHandle(u64);
+
+impl SelectiveChannel {
+    /// Make an empty `SelectiveChannel`. Sub-channels are chosen by the load
+    /// balancer named `load_balancer_name` (e.g. `"rr"`, `"wrr"`, `"random"`
+    /// or `"la"`). The timeout, the retry limit and the backup request delay
+    /// of `options` apply to calls across sub-channels. Fails with
+    /// `BrpcError::ECHANNEL` if e.g. the load balancer is unknown.
+    pub fn with_options(load_balancer_name: &str, options: &ChannelOptions) -> BrpcResult<Self> {
+        let load_balancer_name =
+            CString::new(load_balancer_name).map_err(|_| BrpcError::ECHANNEL)?;
+        let channel = SelectiveChannel {
+            inner: unsafe { ffi::brpc_selective_channel_new() },
+            _retry_policy: options.retry_policy.clone(),
+            channels: HashMap::new(),
+        };
+        let ret = unsafe {
+            ffi::brpc_selective_channel_init(
+                channel.inner,
+                load_balancer_name.as_ptr(),
+                options.inner,
+            )
+        };
+        if ret == 0 {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+
+    /// Add a sub-channel, which is owned by the `SelectiveChannel` from now
+    /// on.
+    pub fn add_channel(&mut self, channel: Channel) -> BrpcResult<ChannelHandle> {
+        // Destroyed by `inner` once added
+        let mut channel = ManuallyDrop::new(channel);
+        let mut handle = 0;
+        let ret = unsafe {
+            ffi::brpc_selective_channel_add_channel(self.inner, channel.inner, &mut handle)
+        };
+        if ret != 0 {
+            ManuallyDrop::into_inner(channel);
+            return Err(BrpcError::EINTERNAL);
+        }
+        let handle = ChannelHandle(handle);
+        self.channels.insert(handle, channel._retry_policy.take());
+        Ok(handle)
+    }
+
+    /// Remove and destroy the sub-channel added as `handle`, along with its
+    /// retry policy. Calls in flight over it are not interrupted. Fails with
+    /// `BrpcError::ECHANNEL` if there is no such sub-channel.
+    pub fn remove_channel(&mut self, handle: ChannelHandle) -> BrpcResult<()> {
+        if self.channels.remove(&handle).is_none() {
+            return Err(BrpcError::ECHANNEL);
+        }
+        unsafe { ffi::brpc_selective_channel_remove_channel(self.inner, handle.0) };
+        Ok(())
+    }
+}
+
+impl Drop for SelectiveChannel {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_selective_channel_destroy(self.inner);
+        }
+    }
+}
+
+unsafe impl ChannelBase for SelectiveChannel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        unsafe { ffi::brpc_selective_channel_as_rpc_channel(self.inner) }
+    }
+}
+
+/// Partition served by a server, see `PartitionParser`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// Index of the partition, in `0..num_partition_kinds`
+    pub index: usize,
+    /// Number of partitions the data is split into
+    pub num_partition_kinds: usize,
+}
+
+/// Tells the partition served by a server of a `PartitionChannel` or a
+/// `DynamicPartitionChannel` from the tag of the server in the naming service,
+/// e.g. `"1/3"` in the line `"127.0.0.1:8000 1/3"` of a `file://` naming
+/// service.
+pub trait PartitionParser: Send + Sync + 'static {
+    /// Parse `tag`, `None` if the server does not serve a partition.
+    fn parse(&self, tag: &str) -> Option<Partition>;
+}
+
+extern "C" fn parse_partition(
+    arg: *mut c_void,
+    tag: *const c_char,
+    index: *mut c_int,
+    num_partition_kinds: *mut c_int,
+) -> c_int {
+    let parser = unsafe { &*(arg as *const Box<dyn PartitionParser>) };
+    let tag = unsafe { CStr::from_ptr(tag) }.to_string_lossy();
+    match catch_panic(|| Ok(parser.parse(&tag))) {
+        Ok(Some(partition)) => {
+            unsafe {
+                *index = partition.index as c_int;
+                *num_partition_kinds = partition.num_partition_kinds as c_int;
+            }
+            1
+        }
+        _ => 0,
+    }
+}
+
+extern "C" fn drop_partition_parser(arg: *mut c_void) {
+    drop(unsafe { Box::from_raw(arg as *mut Box<dyn PartitionParser>) });
+}
+
+/// A `PartitionChannel` sends each call to one server of every partition
+/// among the servers of a naming service, and merges their responses into the
+/// response of the call.
+pub struct PartitionChannel {
+    inner: *mut BrpcPartitionChannel,
+    _retry_policy: Option<Arc<RetryPolicyHandle>>,
+}
+
+impl PartitionChannel {
+    /// Make a `PartitionChannel` to the servers discovered by the naming
+    /// service at `naming_service_url`. Their partitions are told by `parser`,
+    /// and only servers of data split into `num_partition_kinds` partitions are
+    /// called. Calls within a partition are balanced by the load balancer named
+    /// `load_balancer_name`. Fails with `BrpcError::ECHANNEL` if e.g. the
+    /// naming service or the load balancer is unknown.
+    pub fn with_options<P: PartitionParser>(
+        num_partition_kinds: usize,
+        parser: P,
+        naming_service_url: &str,
+        load_balancer_name: &str,
+        options: &PartitionChannelOptions,
+    ) -> BrpcResult<Self> {
+        let naming_service_url =
+            CString::new(naming_service_url).map_err(|_| BrpcError::ECHANNEL)?;
+        let load_balancer_name =
+            CString::new(load_balancer_name).map_err(|_| BrpcError::ECHANNEL)?;
+        let channel = PartitionChannel {
+            inner: unsafe { ffi::brpc_partition_channel_new() },
+            _retry_policy: options.retry_policy.clone(),
+        };
+        let parser: Box<dyn PartitionParser> = Box::new(parser);
+        let ret = unsafe {
+            ffi::brpc_partition_channel_init(
+                channel.inner,
+                num_partition_kinds as c_int,
+                parse_partition,
+                Box::into_raw(Box::new(parser)) as *mut c_void,
+                drop_partition_parser,
+                naming_service_url.as_ptr(),
+                load_balancer_name.as_ptr(),
+                options.inner,
+            )
+        };
+        if ret == 0 {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+
+    /// Number of partitions, `num_partition_kinds`.
+    pub fn partition_count(&self) -> usize {
+        unsafe { ffi::brpc_partition_channel_partition_count(self.inner) as usize }
+    }
+}
+
+impl Drop for PartitionChannel {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_partition_channel_destroy(self.inner);
+        }
+    }
+}
+
+unsafe impl ChannelBase for PartitionChannel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        unsafe { ffi::brpc_partition_channel_as_rpc_channel(self.inner) }
+    }
+}
+
+/// A `DynamicPartitionChannel` is a `PartitionChannel` whose number of
+/// partitions may change, e.g. while data is being re-partitioned. Calls are
+/// balanced across the partitioning schemes found in the naming service,
+/// according to the number of servers of each.
+pub struct DynamicPartitionChannel {
+    inner: *mut BrpcDynamicPartitionChannel,
+    _retry_policy: Option<Arc<RetryPolicyHandle>>,
+}
+
+impl DynamicPartitionChannel {
+    /// Make a `DynamicPartitionChannel` to the servers discovered by the
+    /// naming service at `naming_service_url`, whose partitions are told by
+    /// `parser`. Calls within a partition are balanced by the load balancer
+    /// named `load_balancer_name`. Fails with `BrpcError::ECHANNEL` if e.g.
+    /// the naming service or the load balancer is unknown.
+    pub fn with_options<P: PartitionParser>(
+        parser: P,
+        naming_service_url: &str,
+        load_balancer_name: &str,
+        options: &PartitionChannelOptions,
+    ) -> BrpcResult<Self> {
+        let naming_service_url =
+            CString::new(naming_service_url).map_err(|_| BrpcError::ECHANNEL)?;
+        let load_balancer_name =
+            CString::new(load_balancer_name).map_err(|_| BrpcError::ECHANNEL)?;
+        let channel = DynamicPartitionChannel {
+            inner: unsafe { ffi::brpc_dynamic_partition_channel_new() },
+            _retry_policy: options.retry_policy.clone(),
+        };
+        let parser: Box<dyn PartitionParser> = Box::new(parser);
+        let ret = unsafe {
+            ffi::brpc_dynamic_partition_channel_init(
+                channel.inner,
+                parse_partition,
+                Box::into_raw(Box::new(parser)) as *mut c_void,
+                drop_partition_parser,
+                naming_service_url.as_ptr(),
+                load_balancer_name.as_ptr(),
+                options.inner,
+            )
+        };
+        if ret == 0 {
+            Ok(channel)
+        } else {
+            Err(BrpcError::ECHANNEL)
+        }
+    }
+}
+
+impl Drop for DynamicPartitionChannel {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_dynamic_partition_channel_destroy(self.inner);
+        }
+    }
+}
+
+unsafe impl ChannelBase for DynamicPartitionChannel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        unsafe { ffi::brpc_dynamic_partition_channel_as_rpc_channel(self.inner) }
+    }
+}
+
+/// Options for a `PartitionChannel` or a `DynamicPartitionChannel`
+pub struct PartitionChannelOptions {
+    #[doc(hidden)]
+    pub inner: *mut BrpcPartitionChannelOptions,
+    retry_policy: Option<Arc<RetryPolicyHandle>>,
+}
+
+impl Default for PartitionChannelOptions {
+    fn default() -> Self {
+        Self::new(&ChannelOptions::new())
+    }
+}
+
+impl PartitionChannelOptions {
+    /// Make a `PartitionChannelOptions` with the timeout, the retry limit, the
+    /// protocol and the other settings of `options`, which apply to the calls
+    /// to each partition.
+    pub fn new(options: &ChannelOptions) -> Self {
+        PartitionChannelOptions {
+            inner: unsafe { ffi::brpc_partition_channel_options_new(options.inner) },
+            retry_policy: options.retry_policy.clone(),
+        }
+    }
+
+    /// Fail a call with `BrpcError::ETOOMANYFAILS` as soon as calls to
+    /// `fail_limit` partitions have failed. <=0 means the call fails only if
+    /// all of them fail, which is the default.
+    pub fn set_fail_limit(&mut self, fail_limit: i32) {
+        unsafe {
+            ffi::brpc_partition_channel_options_set_fail_limit(self.inner, fail_limit as c_int)
+        }
+    }
+
+    /// Whether calls succeed, with an empty response, while the naming service
+    /// lists no server. The default is `true`.
+    pub fn set_succeed_without_server(&mut self, succeed_without_server: bool) {
+        unsafe {
+            ffi::brpc_partition_channel_options_set_succeed_without_server(
+                self.inner,
+                succeed_without_server as c_int,
+            )
+        }
+    }
+
+    /// Decide what each partition is sent. The index of a partition is passed
+    /// to `CallMapper::map()` as `channel_index`. By default every partition
+    /// is sent the request of the call unchanged.
+    pub fn set_call_mapper<M: CallMapper>(&mut self, mapper: M) {
+        let mapper: Box<dyn CallMapper> = Box::new(mapper);
+        unsafe {
+            ffi::brpc_partition_channel_options_set_call_mapper(
+                self.inner,
+                map_call,
+                Box::into_raw(Box::new(mapper)) as *mut c_void,
+                drop_call_mapper,
+            )
+        }
+    }
+
+    /// Merge the responses of the partitions. By default they are merged with
+    /// protobuf merge semantics.
+    pub fn set_response_merger<R: ResponseMerger>(&mut self, merger: R) {
+        let merger: Box<dyn ResponseMerger> = Box::new(merger);
+        unsafe {
+            ffi::brpc_partition_channel_options_set_response_merger(
+                self.inner,
+                merge_response,
+                Box::into_raw(Box::new(merger)) as *mut c_void,
+                drop_response_merger,
+            )
+        }
+    }
+}
+
+impl Drop for PartitionChannelOptions {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_partition_channel_options_destroy(self.inner);
+        }
+    }
+}
+
+extern "C" fn call_mock(
+    arg: *mut c_void,
+    method: *const c_char,
+    cntl: *mut BrpcController,
+    request: *const BrpcIOBuf,
+    response: *mut BrpcIOBuf,
+) {
+    let handler = unsafe { &*(arg as *const Box<MockHandler>) };
+    let method = unsafe { CStr::from_ptr(method) }.to_string_lossy();
+    let request = unsafe { iobuf_to_vec(request) };
+    match catch_panic(|| handler(&method, &request)) {
+        Ok(data) => unsafe {
+            ffi::brpc_iobuf_assign(response, data.as_ptr() as *const c_void, data.len())
+        },
+        Err(status) => controller::set_failed(cntl, &status),
+    }
+}
+
+extern "C" fn drop_mock_handler(arg: *mut c_void) {
+    drop(unsafe { Box::from_raw(arg as *mut Box<MockHandler>) });
+}
+
+type MockHandler = dyn Fn(&str, &[u8]) -> RpcResult<Vec<u8>> + Send + Sync;
+
+/// A `MockChannel` serves calls in-process instead of sending them to a
+/// server, e.g. to test clients without a server.
+pub struct MockChannel {
+    inner: *mut BrpcRpcChannel,
+}
+
+impl MockChannel {
+    /// Make a `MockChannel` whose calls are served by `handler`. It is passed
+    /// the full name of the method (e.g. `"example.EchoService.echo"`) and the
+    /// protobuf-encoded request, and returns the protobuf-encoded response.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &[u8]) -> RpcResult<Vec<u8>> + Send + Sync + 'static,
+    {
+        let handler: Box<MockHandler> = Box::new(handler);
+        let inner = unsafe {
+            ffi::brpc_rust_channel_new(
+                call_mock,
+                Box::into_raw(Box::new(handler)) as *mut c_void,
+                drop_mock_handler,
+            )
+        };
+        MockChannel { inner }
+    }
+}
+
+impl Drop for MockChannel {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_rust_channel_destroy(self.inner);
+        }
+    }
+}
+
+unsafe impl ChannelBase for MockChannel {
+    fn rpc_channel(&self) -> *mut BrpcRpcChannel {
+        self.inner
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::ptr;
+    use crate::Controller;
+    use brpc_sys::zero_copy::IOBuf;
 
     #[test]
     fn channel_options_new() {
@@ -108,6 +1092,77 @@ mod tests {
     }
 
     #[test]
+    fn channel_options_set_protocol() {
+        let mut opt = ChannelOptions::new();
+        opt.set_protocol(Protocol::BaiduStd);
+        opt.set_protocol(Protocol::Grpc);
+    }
+
+    #[test]
+    fn channel_options_set_connection() {
+        let mut opt = ChannelOptions::new();
+        opt.set_connection_type(ConnectionType::Pooled);
+        opt.set_connection_type(ConnectionType::Short);
+        opt.set_connect_timeout_ms(100);
+        assert_eq!(Ok(()), opt.set_connection_group("hot"));
+        assert_eq!(Err(BrpcError::EARGUMENT), opt.set_connection_group("h\0t"));
+    }
+
+    struct RetryAll;
+
+    impl RetryPolicy for RetryAll {
+        fn do_retry(&self, _cntl: &Controller) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn channel_options_set_retry_policy() {
+        let mut opt = ChannelOptions::new();
+        opt.set_retry_policy(RetryAll);
+        let ch = Channel::new("127.0.0.1:50000", &opt).unwrap();
+        // The channel shares the policy with the options
+        drop(opt);
+        assert!(ch._retry_policy.is_some());
+    }
+
+    #[test]
+    fn channel_options_set_backup_request_ms() {
+        let mut opt = ChannelOptions::new();
+        opt.set_backup_request_ms(10);
+        opt.set_backup_request_ms(-1);
+    }
+
+    #[test]
+    fn channel_ssl_options() {
+        let mut ssl = ChannelSslOptions::new();
+        assert_ne!(ssl.inner, ptr::null_mut());
+        ssl.set_ca_file("/etc/ssl/certs/ca-certificates.crt")
+            .unwrap();
+        ssl.set_verify_depth(1);
+        ssl.set_client_certificate("client.crt", "client.key")
+            .unwrap();
+        ssl.set_sni_name("echo.example.com").unwrap();
+        ssl.set_ciphers("ECDHE-RSA-AES256-GCM-SHA384").unwrap();
+        let err = Some(BrpcError::EARGUMENT);
+        assert_eq!(err, ssl.set_ca_file("ca\0.pem").err());
+        assert_eq!(err, ssl.set_client_certificate("client.crt", "\0").err());
+        assert_eq!(err, ssl.set_sni_name("echo\0").err());
+        assert_eq!(err, ssl.set_ciphers("\0").err());
+        let mut opt = ChannelOptions::new();
+        opt.set_ssl_options(&ssl);
+        drop(ssl);
+        // The client certificate files do not exist
+        let addr: SocketAddrV4 = "127.0.0.1:50000".parse().unwrap();
+        assert_eq!(Some(BrpcError::ECHANNEL), Channel::new(&addr, &opt).err());
+
+        let mut opt = ChannelOptions::new();
+        opt.set_ssl_options(&ChannelSslOptions::new());
+        assert!(Channel::new(&addr, &opt).is_ok());
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn channel_new_with_options() {
         let opt = ChannelOptions::new();
         let addr = "127.0.0.1:50000".parse().unwrap();
@@ -115,4 +1170,243 @@ mod tests {
         assert_ne!(opt.inner, ptr::null_mut());
         assert_ne!(ch.inner, ptr::null_mut());
     }
+
+    #[test]
+    fn channel_new() {
+        let opt = ChannelOptions::new();
+        let addr: SocketAddrV4 = "127.0.0.1:50000".parse().unwrap();
+        assert!(Channel::new(&addr, &opt).is_ok());
+        assert!(Channel::new("127.0.0.1:50000", &opt).is_ok());
+        assert!(Channel::new(&"localhost:50000".to_owned(), &opt).is_ok());
+    }
+
+    #[test]
+    fn channel_new_invalid_endpoint() {
+        let opt = ChannelOptions::new();
+        for endpoint in &["not an endpoint", "127.0.0.1", "127.0.0.1:50000\0"] {
+            let err = Channel::new(*endpoint, &opt).err();
+            assert_eq!(Some(BrpcError::ECHANNEL), err);
+        }
+    }
+
+    #[test]
+    fn channel_new_invalid_naming_service() {
+        let opt = ChannelOptions::new();
+        let err =
+            Channel::new_with_naming_service("list://127.0.0.1:50000", "no_such_lb", &opt).err();
+        assert_eq!(Some(BrpcError::ECHANNEL), err);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn channel_new_with_naming_service() {
+        let opt = ChannelOptions::new();
+        let ch = Channel::with_naming_service("list://127.0.0.1:50000,127.0.0.1:50001", "rr", &opt);
+        assert_ne!(ch.inner, ptr::null_mut());
+    }
+
+    struct Forward;
+
+    impl CallMapper for Forward {
+        fn map(&self, _channel_index: usize, _method: &str, _request: &[u8]) -> SubCall {
+            SubCall::Forward
+        }
+    }
+
+    struct Concat;
+
+    impl ResponseMerger for Concat {
+        fn merge(&self, response: &mut Vec<u8>, sub_response: &[u8]) -> MergeResult {
+            response.extend_from_slice(sub_response);
+            MergeResult::Merged
+        }
+    }
+
+    #[test]
+    fn parallel_channel_options() {
+        let mut opt = ParallelChannelOptions::new();
+        assert_ne!(opt.inner, ptr::null_mut());
+        opt.set_timeout_ms(100);
+        opt.set_fail_limit(1);
+        opt.set_success_limit(1);
+    }
+
+    #[test]
+    fn parallel_channel_add_channel() {
+        let mut pchan = ParallelChannel::with_options(&ParallelChannelOptions::new()).unwrap();
+        assert_eq!(0, pchan.channel_count());
+        let opt = ChannelOptions::new();
+        for port in &[50000, 50001] {
+            let ch = Channel::new(&format!("127.0.0.1:{}", port), &opt).unwrap();
+            pchan
+                .add_channel(ch, Some(Box::new(Forward)), Some(Box::new(Concat)))
+                .unwrap();
+        }
+        pchan
+            .add_channel(Channel::new("127.0.0.1:50002", &opt).unwrap(), None, None)
+            .unwrap();
+        assert_eq!(3, pchan.channel_count());
+        assert_ne!(pchan.rpc_channel(), ptr::null_mut());
+    }
+
+    struct Replace;
+
+    impl ResponseMerger for Replace {
+        fn merge(&self, response: &mut Vec<u8>, sub_response: &[u8]) -> MergeResult {
+            *response = sub_response.to_vec();
+            MergeResult::Merged
+        }
+    }
+
+    fn merge(
+        merger: Box<dyn ResponseMerger>,
+        response: &[u8],
+        sub_response: &[u8],
+    ) -> (c_int, Vec<u8>) {
+        let (response_buf, sub_response_buf) = (IOBuf::new(), IOBuf::new());
+        unsafe {
+            ffi::brpc_iobuf_assign(
+                response_buf.as_ptr(),
+                response.as_ptr() as *const c_void,
+                response.len(),
+            );
+            ffi::brpc_iobuf_assign(
+                sub_response_buf.as_ptr(),
+                sub_response.as_ptr() as *const c_void,
+                sub_response.len(),
+            );
+        }
+        let arg = Box::into_raw(Box::new(merger)) as *mut c_void;
+        let result = merge_response(arg, response_buf.as_ptr(), sub_response_buf.as_ptr());
+        drop_response_merger(arg);
+        (result, unsafe { iobuf_to_vec(response_buf.as_ptr()) })
+    }
+
+    #[test]
+    fn merge_response_hands_back_appended_bytes() {
+        assert_eq!(
+            (MERGE_APPENDED, b"\x08\x02".to_vec()),
+            merge(Box::new(Concat), b"\x08\x01", b"\x08\x02")
+        );
+        assert_eq!(
+            (MergeResult::Merged as c_int, b"\x08\x02".to_vec()),
+            merge(Box::new(Replace), b"\x08\x01", b"\x08\x02")
+        );
+    }
+
+    #[test]
+    fn selective_channel_add_remove_channel() {
+        let opt = ChannelOptions::new();
+        let mut schan = SelectiveChannel::with_options("rr", &opt).unwrap();
+        let first = schan
+            .add_channel(Channel::new("127.0.0.1:50000", &opt).unwrap())
+            .unwrap();
+        let second = schan
+            .add_channel(
+                Channel::new_with_naming_service("list://127.0.0.1:50001", "rr", &opt).unwrap(),
+            )
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(Ok(()), schan.remove_channel(first));
+        assert_eq!(Err(BrpcError::ECHANNEL), schan.remove_channel(first));
+        assert_ne!(schan.rpc_channel(), ptr::null_mut());
+    }
+
+    #[test]
+    fn selective_channel_owns_sub_channel_retry_policy() {
+        let policy = Arc::new(RetryAll);
+        let channel = || {
+            let mut opt = ChannelOptions::new();
+            opt.set_retry_policy(policy.clone());
+            Channel::new("127.0.0.1:50000", &opt).unwrap()
+        };
+        let mut schan = SelectiveChannel::with_options("rr", &ChannelOptions::new()).unwrap();
+        let first = schan.add_channel(channel()).unwrap();
+        assert_eq!(2, Arc::strong_count(&policy));
+        schan.remove_channel(first).unwrap();
+        assert_eq!(1, Arc::strong_count(&policy));
+
+        schan.add_channel(channel()).unwrap();
+        assert_eq!(2, Arc::strong_count(&policy));
+        drop(schan);
+        assert_eq!(1, Arc::strong_count(&policy));
+    }
+
+    #[test]
+    fn selective_channel_invalid_load_balancer() {
+        let opt = ChannelOptions::new();
+        assert_eq!(
+            Some(BrpcError::ECHANNEL),
+            SelectiveChannel::with_options("r\0r", &opt).err()
+        );
+    }
+
+    struct Fraction;
+
+    impl PartitionParser for Fraction {
+        fn parse(&self, tag: &str) -> Option<Partition> {
+            let mut parts = tag.splitn(2, '/');
+            Some(Partition {
+                index: parts.next()?.parse().ok()?,
+                num_partition_kinds: parts.next()?.parse().ok()?,
+            })
+        }
+    }
+
+    #[test]
+    fn partition_channel_options() {
+        let mut opt = PartitionChannelOptions::new(&ChannelOptions::new());
+        assert_ne!(opt.inner, ptr::null_mut());
+        opt.set_fail_limit(1);
+        opt.set_succeed_without_server(false);
+        opt.set_call_mapper(Forward);
+        opt.set_response_merger(Concat);
+    }
+
+    #[test]
+    fn partition_channel_new() {
+        let opt = PartitionChannelOptions::default();
+        let pchan = PartitionChannel::with_options(
+            2,
+            Fraction,
+            "list://127.0.0.1:50000 0/2,127.0.0.1:50001 1/2",
+            "rr",
+            &opt,
+        )
+        .unwrap();
+        assert_eq!(2, pchan.partition_count());
+        assert_ne!(pchan.rpc_channel(), ptr::null_mut());
+    }
+
+    #[test]
+    fn dynamic_partition_channel_new() {
+        let opt = PartitionChannelOptions::default();
+        let dchan = DynamicPartitionChannel::with_options(
+            Fraction,
+            "list://127.0.0.1:50000 0/2,127.0.0.1:50001 1/2",
+            "rr",
+            &opt,
+        )
+        .unwrap();
+        assert_ne!(dchan.rpc_channel(), ptr::null_mut());
+    }
+
+    #[test]
+    fn partition_channel_invalid_url() {
+        let opt = PartitionChannelOptions::default();
+        assert_eq!(
+            Some(BrpcError::ECHANNEL),
+            PartitionChannel::with_options(2, Fraction, "list://\0", "rr", &opt).err()
+        );
+        assert_eq!(
+            Some(BrpcError::ECHANNEL),
+            DynamicPartitionChannel::with_options(Fraction, "list://\0", "rr", &opt).err()
+        );
+    }
+
+    #[test]
+    fn mock_channel_new() {
+        let ch = MockChannel::new(|_method, request| Ok(request.to_vec()));
+        assert_ne!(ch.rpc_channel(), ptr::null_mut());
+    }
 }
@@ -0,0 +1,179 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brpc_sys::ffi::{self, BrpcController};
+use libc::{c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::slice;
+use std::time::Duration;
+
+// An endpoint of BRPC, which is unset (0.0.0.0:0) until a connection is made.
+// butil 0.9.6 endpoints are IPv4 only.
+pub(crate) fn socket_addr(ip: u32, port: c_int) -> Option<SocketAddr> {
+    if ip == 0 && port == 0 {
+        return None;
+    }
+    Some(SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::from(ip),
+        port as u16,
+    )))
+}
+
+/// A `ServerContext` carries information about the RPC being served. It is
+/// only valid until the RPC is responded to.
+pub struct ServerContext {
+    pub(crate) cntl: *mut BrpcController,
+}
+
+impl ServerContext {
+    #[doc(hidden)]
+    pub unsafe fn from_raw(cntl: *mut BrpcController) -> Self {
+        ServerContext { cntl }
+    }
+
+    /// Address of the client, `None` if it is not known.
+    pub fn remote_side(&self) -> Option<SocketAddr> {
+        let (mut ip, mut port): (u32, c_int) = (0, 0);
+        unsafe { ffi::brpc_controller_remote_side(self.cntl, &mut ip, &mut port) };
+        socket_addr(ip, port)
+    }
+
+    /// Address of the server which the client connected to, `None` if it is
+    /// not known.
+    pub fn local_side(&self) -> Option<SocketAddr> {
+        let (mut ip, mut port): (u32, c_int) = (0, 0);
+        unsafe { ffi::brpc_controller_local_side(self.cntl, &mut ip, &mut port) };
+        socket_addr(ip, port)
+    }
+
+    /// Log id set by the client, 0 if not set.
+    pub fn log_id(&self) -> u64 {
+        unsafe { ffi::brpc_controller_log_id(self.cntl) }
+    }
+
+    /// Value of the HTTP header `name` of an HTTP or h2 request, `None` if it
+    /// is not set or is not valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<String> {
+        let name = CString::new(name).ok()?;
+        let mut len = 0;
+        let value = unsafe { ffi::brpc_controller_http_header(self.cntl, name.as_ptr(), &mut len) };
+        if value.is_null() {
+            return None;
+        }
+        let value = unsafe { slice::from_raw_parts(value as *const u8, len) };
+        String::from_utf8(value.to_vec()).ok()
+    }
+
+    /// Subject of the verified certificate of the client such as
+    /// `"CN=client.example.com,O=Example"`, `None` if the client did not
+    /// present one. See `ServerSslOptions::require_client_certificate()`.
+    pub fn peer_certificate_subject(&self) -> Option<String> {
+        unsafe {
+            let subject = ffi::brpc_controller_peer_certificate_subject(self.cntl);
+            if subject.is_null() {
+                return None;
+            }
+            let text = CStr::from_ptr(subject).to_string_lossy().into_owned();
+            libc::free(subject as *mut c_void);
+            Some(text)
+        }
+    }
+
+    /// Attachment sent by the client along with the request.
+    pub fn request_attachment(&self) -> Vec<u8> {
+        unsafe {
+            let buf = ffi::brpc_controller_get_request_attachment(self.cntl);
+            let mut data = vec![0; ffi::brpc_iobuf_size(buf)];
+            let len = ffi::brpc_iobuf_copy_to(buf, data.as_mut_ptr() as *mut c_void, data.len());
+            data.truncate(len);
+            data
+        }
+    }
+
+    /// Attachment sent to the client along with the response.
+    pub fn set_response_attachment(&mut self, data: &[u8]) {
+        unsafe {
+            let buf = ffi::brpc_controller_get_response_attachment(self.cntl);
+            ffi::brpc_iobuf_assign(buf, data.as_ptr() as *const c_void, data.len());
+        }
+    }
+
+    /// Time left before the deadline set by the client, `None` if the client
+    /// did not set one. A passed deadline is `Some(Duration::from_secs(0))`.
+    pub fn remaining_deadline(&self) -> Option<Duration> {
+        let remaining_us = unsafe { ffi::brpc_controller_remaining_deadline_us(self.cntl) };
+        if remaining_us < 0 {
+            None
+        } else {
+            Some(Duration::from_micros(remaining_us as u64))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Controller;
+
+    #[test]
+    fn server_context_log_id() {
+        let cntl = Controller::new();
+        let ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert_eq!(0, ctx.log_id());
+    }
+
+    #[test]
+    fn server_context_sides() {
+        let cntl = Controller::new();
+        let ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert_eq!(None, ctx.remote_side());
+        assert_eq!(None, ctx.local_side());
+        assert_eq!(
+            Some("127.0.0.1:8000".parse::<SocketAddr>().unwrap()),
+            socket_addr(0x7f00_0001, 8000)
+        );
+    }
+
+    #[test]
+    fn server_context_header() {
+        let cntl = Controller::new();
+        let ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert_eq!(None, ctx.header("x-tenant"));
+    }
+
+    #[test]
+    fn server_context_no_peer_certificate() {
+        let cntl = Controller::new();
+        let ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert_eq!(None, ctx.peer_certificate_subject());
+    }
+
+    #[test]
+    fn server_context_attachments() {
+        let cntl = Controller::new();
+        let mut ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert!(ctx.request_attachment().is_empty());
+        ctx.set_response_attachment(b"first");
+        ctx.set_response_attachment(b"second");
+        let buf = cntl.response_attachment();
+        assert_eq!(6, unsafe { ffi::brpc_iobuf_size(buf) });
+    }
+
+    #[test]
+    fn server_context_no_deadline() {
+        let cntl = Controller::new();
+        let ctx = unsafe { ServerContext::from_raw(cntl.inner) };
+        assert_eq!(None, ctx.remaining_deadline());
+    }
+}
@@ -11,39 +11,169 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::BrpcError;
+use crate::context;
+use crate::retry::{RetryPolicy, RetryPolicyHandle};
+use crate::{BrpcError, RpcStatus};
 use brpc_sys::ffi::{self, BrpcController, BrpcIOBuf};
+use libc::{c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[doc(hidden)]
+/// Compression of the request body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressType {
+    None = 0,
+    Snappy = 1,
+    Gzip = 2,
+    Zlib = 3,
+}
+
+/// A `Controller` holds the options and the outcome of one RPC. Pass it to
+/// the `*_with` methods of client stubs to set options of a single call, and
+/// inspect it after the call returns. Use a new `Controller` for each call.
 pub struct Controller {
+    #[doc(hidden)]
     pub inner: *mut BrpcController,
+    _retry_policy: Option<Arc<RetryPolicyHandle>>,
 }
 
 impl Controller {
+    /// Make a `Controller` with the options of the channel.
     pub fn new() -> Self {
+        unsafe { Self::from_raw(ffi::brpc_controller_new()) }
+    }
+
+    pub(crate) unsafe fn from_raw(inner: *mut BrpcController) -> Self {
         Controller {
-            inner: unsafe { ffi::brpc_controller_new() },
+            inner,
+            _retry_policy: None,
+        }
+    }
+
+    /// Set max duration of this RPC in milliseconds, overriding the timeout
+    /// of the channel. -1 means wait indefinitely.
+    pub fn set_timeout_ms(&mut self, timeout_ms: i64) {
+        unsafe { ffi::brpc_controller_set_timeout_ms(self.inner, timeout_ms) }
+    }
+
+    /// Set retry limit of this RPC, overriding the limit of the channel. <=0
+    /// means no retry.
+    pub fn set_max_retry(&mut self, max_retry: i32) {
+        unsafe { ffi::brpc_controller_set_max_retry(self.inner, max_retry as c_int) }
+    }
+
+    /// Decide whether this RPC is retried with `policy`, overriding the retry
+    /// policy of the channel.
+    pub fn set_retry_policy<P: RetryPolicy + 'static>(&mut self, policy: P) {
+        let policy = RetryPolicyHandle::new(policy);
+        unsafe { ffi::brpc_controller_set_retry_policy(self.inner, policy.inner) };
+        self._retry_policy = Some(policy);
+    }
+
+    /// Set the log id sent to the server, see `ServerContext::log_id()`.
+    pub fn set_log_id(&mut self, log_id: u64) {
+        unsafe { ffi::brpc_controller_set_log_id(self.inner, log_id) }
+    }
+
+    /// Set the code used by consistent hashing load balancers (e.g.
+    /// `"c_murmurhash"`) to choose the server.
+    pub fn set_request_code(&mut self, request_code: u64) {
+        unsafe { ffi::brpc_controller_set_request_code(self.inner, request_code) }
+    }
+
+    /// Send a backup request if the server has not responded within
+    /// `timeout_ms` milliseconds, overriding the setting of the channel. -1
+    /// means no backup request.
+    pub fn set_backup_request_ms(&mut self, timeout_ms: i64) {
+        unsafe { ffi::brpc_controller_set_backup_request_ms(self.inner, timeout_ms) }
+    }
+
+    /// Set the compression of the request body. The default is
+    /// `CompressType::None`.
+    pub fn set_request_compress_type(&mut self, compress_type: CompressType) {
+        unsafe {
+            ffi::brpc_controller_set_request_compress_type(self.inner, compress_type as c_int)
         }
     }
 
+    /// Whether the RPC failed.
     pub fn failed(&self) -> bool {
         unsafe { 1 == ffi::brpc_controller_failed(self.inner) }
     }
 
+    #[doc(hidden)]
     pub fn error(&self) -> BrpcError {
         let error_code = unsafe { ffi::brpc_controller_error_code(self.inner) };
         BrpcError::from(error_code)
     }
 
+    /// Text describing why the RPC failed, empty if it did not fail.
+    pub fn error_text(&self) -> String {
+        unsafe {
+            let text = ffi::brpc_controller_error_text(self.inner);
+            let error_text = CStr::from_ptr(text).to_string_lossy().into_owned();
+            libc::free(text as *mut c_void);
+            error_text
+        }
+    }
+
+    /// How many times the RPC was retried.
+    pub fn retried_count(&self) -> i32 {
+        unsafe { ffi::brpc_controller_retried_count(self.inner) }
+    }
+
+    /// Status of a failed RPC.
+    pub fn status(&self) -> RpcStatus {
+        let code = unsafe { ffi::brpc_controller_error_code(self.inner) };
+        RpcStatus::new(code, &self.error_text()).with_retried_count(self.retried_count())
+    }
+
+    /// Time taken by the RPC, including the backoffs before its retries.
+    pub fn latency(&self) -> Duration {
+        let latency_us = unsafe { ffi::brpc_controller_latency_us(self.inner) };
+        Duration::from_micros(latency_us.max(0) as u64)
+    }
+
+    /// Address of the server which served the RPC, `None` before the call or
+    /// if no server was reached.
+    pub fn remote_side(&self) -> Option<SocketAddr> {
+        let (mut ip, mut port): (u32, c_int) = (0, 0);
+        unsafe { ffi::brpc_controller_remote_side(self.inner, &mut ip, &mut port) };
+        context::socket_addr(ip, port)
+    }
+
+    /// Whether a backup request was sent because the first server did not
+    /// respond in time.
+    pub fn has_backup_request(&self) -> bool {
+        unsafe { 1 == ffi::brpc_controller_has_backup_request(self.inner) }
+    }
+
+    #[doc(hidden)]
     pub fn request_attachment(&self) -> *mut BrpcIOBuf {
         unsafe { ffi::brpc_controller_get_request_attachment(self.inner) }
     }
 
+    #[doc(hidden)]
     pub fn response_attachment(&self) -> *mut BrpcIOBuf {
         unsafe { ffi::brpc_controller_get_response_attachment(self.inner) }
     }
 }
 
+// Fail the RPC of `cntl` with `status`, e.g. the error of a handler
+pub(crate) fn set_failed(cntl: *mut BrpcController, status: &RpcStatus) {
+    // A code of 0 would not fail the RPC
+    let code = match status.code {
+        0 => BrpcError::EINTERNAL as i32,
+        code => code,
+    };
+    // The text sent ends at the first NUL, if any
+    let message = status.message.split('\0').next().unwrap_or_default();
+    let text = CString::new(message).unwrap();
+    unsafe { ffi::brpc_controller_set_failed(cntl, code, text.as_ptr()) };
+}
+
 impl Drop for Controller {
     fn drop(&mut self) {
         unsafe {
@@ -81,6 +211,61 @@ mod tests {
         assert_eq!(BrpcError::NOERROR, cntl.error());
     }
 
+    #[test]
+    fn controller_status() {
+        let cntl = Controller::new();
+        let status = cntl.status();
+        assert_eq!(0, status.code);
+        assert!(status.message.is_empty());
+    }
+
+    #[test]
+    fn controller_set_failed() {
+        let cntl = Controller::new();
+        set_failed(cntl.inner, &RpcStatus::new(10001, "bad\0request"));
+        assert_eq!(RpcStatus::new(10001, "bad"), cntl.status());
+
+        let cntl = Controller::new();
+        set_failed(cntl.inner, &RpcStatus::new(0, "failed"));
+        assert_eq!(BrpcError::EINTERNAL, cntl.status().error());
+    }
+
+    #[test]
+    fn controller_set_options() {
+        let mut cntl = Controller::new();
+        cntl.set_timeout_ms(100);
+        cntl.set_max_retry(0);
+        cntl.set_log_id(42);
+        cntl.set_request_code(7);
+        cntl.set_backup_request_ms(10);
+        cntl.set_request_compress_type(CompressType::Gzip);
+    }
+
+    struct NoRetry;
+
+    impl RetryPolicy for NoRetry {
+        fn do_retry(&self, _cntl: &Controller) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn controller_set_retry_policy() {
+        let mut cntl = Controller::new();
+        cntl.set_retry_policy(NoRetry);
+        // Replacing the policy frees the previous one
+        cntl.set_retry_policy(Arc::new(NoRetry));
+    }
+
+    #[test]
+    fn controller_before_call() {
+        let cntl = Controller::new();
+        assert_eq!(0, cntl.retried_count());
+        assert!(!cntl.has_backup_request());
+        assert_eq!(Duration::from_micros(0), cntl.latency());
+        assert_eq!(None, cntl.remote_side());
+    }
+
     #[test]
     fn controller_get_request_attachment() {
         let cntl = Controller::new();
@@ -11,6 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::error::Error;
+use std::fmt;
+
 #[repr(C)]
 #[derive(PartialEq, Clone, Debug)]
 #[allow(dead_code)]
@@ -49,6 +52,9 @@ pub enum BrpcError {
     ESERIALIZE = 3001,   // Prost serialization error
     EDESERIALIZE = 3002, // Prost deserialization error
     EFFI = 3003,         // FFI error
+    EPANIC = 3004,       // Handler panicked
+    ECHANNEL = 3005,     // Failed to initialize a channel
+    EARGUMENT = 3006,    // Invalid argument
 
     UNKNOWN = 0xffff, // Unknown error,
 }
@@ -87,16 +93,152 @@ impl From<i32> for BrpcError {
             3001 => BrpcError::ESERIALIZE,
             3002 => BrpcError::EDESERIALIZE,
             3003 => BrpcError::EFFI,
+            3004 => BrpcError::EPANIC,
+            3005 => BrpcError::ECHANNEL,
+            3006 => BrpcError::EARGUMENT,
 
             _ => BrpcError::UNKNOWN,
         }
     }
 }
 
+impl fmt::Display for BrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} ({})", self, self.clone() as i32)
+    }
+}
+
+impl Error for BrpcError {}
+
+/// The status of a failed RPC. `code` is either a `BrpcError`, a system errno
+/// such as `ECONNREFUSED` or defined by the application, and must not be 0.
+/// Both `code` and `message` set by a server handler are received by the
+/// client.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RpcStatus {
+    pub code: i32,
+    pub message: String,
+    retried_count: i32,
+}
+
+impl RpcStatus {
+    /// Make a `RpcStatus` with an application defined `code`.
+    pub fn new(code: i32, message: &str) -> Self {
+        RpcStatus {
+            code,
+            message: message.to_owned(),
+            retried_count: 0,
+        }
+    }
+
+    pub(crate) fn with_retried_count(mut self, retried_count: i32) -> Self {
+        self.retried_count = retried_count;
+        self
+    }
+
+    /// The `BrpcError` of `code`, `BrpcError::UNKNOWN` for system errnos and
+    /// application defined codes.
+    pub fn error(&self) -> BrpcError {
+        BrpcError::from(self.code)
+    }
+
+    /// Whether the RPC failed because it timed out.
+    pub fn is_timeout(&self) -> bool {
+        self.code == BrpcError::ERPCTIMEDOUT as i32 || self.code == libc::ETIMEDOUT
+    }
+
+    /// Whether the RPC failed because the server could not be connected or
+    /// the connection was broken.
+    pub fn is_connection_error(&self) -> bool {
+        [
+            BrpcError::EFAILEDSOCKET as i32,
+            BrpcError::EEOF as i32,
+            libc::ECONNREFUSED,
+            libc::ECONNRESET,
+            libc::ECONNABORTED,
+            libc::EHOSTDOWN,
+            libc::EHOSTUNREACH,
+            libc::ENETDOWN,
+            libc::ENETUNREACH,
+            libc::EPIPE,
+        ]
+        .contains(&self.code)
+    }
+
+    /// How many times the client retried the RPC before it failed.
+    pub fn retried_count(&self) -> i32 {
+        self.retried_count
+    }
+}
+
+impl From<BrpcError> for RpcStatus {
+    fn from(e: BrpcError) -> RpcStatus {
+        RpcStatus::new(e as i32, "")
+    }
+}
+
+impl fmt::Display for RpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "RPC failed with code {}", self.code)
+        } else {
+            write!(f, "{} (code {})", self.message, self.code)
+        }
+    }
+}
+
+impl Error for RpcStatus {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn rpc_status_from_brpc_error() {
+        let status = RpcStatus::from(BrpcError::ELIMIT);
+        assert_eq!(2004, status.code);
+        assert_eq!(BrpcError::ELIMIT, status.error());
+        assert!(status.message.is_empty());
+    }
+
+    #[test]
+    fn rpc_status_is_timeout() {
+        assert!(RpcStatus::from(BrpcError::ERPCTIMEDOUT).is_timeout());
+        assert!(RpcStatus::new(libc::ETIMEDOUT, "").is_timeout());
+        assert!(!RpcStatus::from(BrpcError::EINTERNAL).is_timeout());
+    }
+
+    #[test]
+    fn rpc_status_is_connection_error() {
+        assert!(RpcStatus::new(libc::ECONNREFUSED, "").is_connection_error());
+        assert!(RpcStatus::from(BrpcError::EFAILEDSOCKET).is_connection_error());
+        assert!(!RpcStatus::from(BrpcError::ERPCTIMEDOUT).is_connection_error());
+    }
+
+    #[test]
+    fn rpc_status_display() {
+        let status = RpcStatus::new(10001, "no such user");
+        assert_eq!("no such user (code 10001)", status.to_string());
+        let status = RpcStatus::from(BrpcError::ELIMIT);
+        assert_eq!("RPC failed with code 2004", status.to_string());
+    }
+
+    #[test]
+    fn brpc_error_display() {
+        assert_eq!("ELIMIT (2004)", BrpcError::ELIMIT.to_string());
+        let e: Box<dyn Error> = Box::new(BrpcError::ELIMIT);
+        assert_eq!("ELIMIT (2004)", e.to_string());
+    }
+
+    #[test]
+    fn rpc_status_new() {
+        let status = RpcStatus::new(10001, "no such user");
+        assert_eq!(10001, status.code);
+        assert_eq!("no such user", status.message);
+        assert_eq!(BrpcError::UNKNOWN, status.error());
+        assert_eq!(0, status.retried_count());
+    }
+
     #[test]
     fn from_i32() {
         assert_eq!(BrpcError::from(0), BrpcError::NOERROR);
@@ -127,6 +269,9 @@ mod tests {
         assert_eq!(BrpcError::from(3001), BrpcError::ESERIALIZE);
         assert_eq!(BrpcError::from(3002), BrpcError::EDESERIALIZE);
         assert_eq!(BrpcError::from(3003), BrpcError::EFFI);
+        assert_eq!(BrpcError::from(3004), BrpcError::EPANIC);
+        assert_eq!(BrpcError::from(3005), BrpcError::ECHANNEL);
+        assert_eq!(BrpcError::from(3006), BrpcError::EARGUMENT);
         assert_eq!(BrpcError::from(5678), BrpcError::UNKNOWN);
     }
 }
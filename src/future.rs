@@ -0,0 +1,176 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{BrpcError, BrpcResult, Controller, RpcResult, RpcStatus};
+use brpc_sys::ffi::{self, BrpcController, BrpcIOBuf};
+use brpc_sys::zero_copy::IOBuf;
+use libc::c_void;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// Everything BRPC touches during an asynchronous RPC. It is shared with the
+// `done` callback so that it outlives the RPC even if the future is dropped.
+struct CallState {
+    cntl: Controller,
+    request: IOBuf,
+    response: IOBuf,
+    done: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+// The controller and buffers are only touched by BRPC before `done` is set
+// and only by the future after that.
+unsafe impl Send for CallState {}
+unsafe impl Sync for CallState {}
+
+/// A `Future` which resolves to the response of an asynchronous RPC, returned
+/// by the `*_async` methods of client stubs. Dropping it before completion
+/// cancels the RPC.
+pub struct ResponseFuture<T> {
+    state: Arc<CallState>,
+    decode: fn(*mut BrpcIOBuf) -> BrpcResult<T>,
+    error: Option<BrpcError>,
+}
+
+impl<T> ResponseFuture<T> {
+    #[doc(hidden)]
+    pub fn new(decode: fn(*mut BrpcIOBuf) -> BrpcResult<T>) -> Self {
+        ResponseFuture {
+            state: Arc::new(CallState {
+                cntl: Controller::new(),
+                request: IOBuf::new(),
+                response: IOBuf::new(),
+                done: AtomicBool::new(false),
+                waker: Mutex::new(None),
+            }),
+            decode,
+            error: None,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn controller(&self) -> *mut BrpcController {
+        self.state.cntl.inner
+    }
+
+    #[doc(hidden)]
+    pub fn request_buf(&self) -> *mut BrpcIOBuf {
+        self.state.request.as_ptr()
+    }
+
+    #[doc(hidden)]
+    pub fn response_buf(&self) -> *mut BrpcIOBuf {
+        self.state.response.as_ptr()
+    }
+
+    /// Fail the future before the RPC is issued.
+    #[doc(hidden)]
+    pub fn fail(&mut self, error: BrpcError) {
+        self.error = Some(error);
+    }
+
+    /// Callback and its argument to be passed to BRPC as the `done` closure.
+    /// The callback must be invoked exactly once.
+    #[doc(hidden)]
+    pub fn done_callback(&self) -> (extern "C" fn(*mut c_void), *mut c_void) {
+        let arg = Arc::into_raw(self.state.clone()) as *mut c_void;
+        (on_done, arg)
+    }
+}
+
+extern "C" fn on_done(arg: *mut c_void) {
+    let state = unsafe { Arc::from_raw(arg as *const CallState) };
+    state.done.store(true, Ordering::Release);
+    let waker = state.waker.lock().unwrap().take();
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+impl<T> Future for ResponseFuture<T> {
+    type Output = RpcResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(error) = self.error.take() {
+            return Poll::Ready(Err(error.into()));
+        }
+        // Register the waker before checking `done` so that a completion in
+        // between is not missed.
+        *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        if !self.state.done.load(Ordering::Acquire) {
+            return Poll::Pending;
+        }
+        if self.state.cntl.failed() {
+            return Poll::Ready(Err(self.state.cntl.status()));
+        }
+        Poll::Ready((self.decode)(self.state.response.as_ptr()).map_err(RpcStatus::from))
+    }
+}
+
+impl<T> Drop for ResponseFuture<T> {
+    fn drop(&mut self) {
+        if self.error.is_none() && !self.state.done.load(Ordering::Acquire) {
+            unsafe { ffi::brpc_controller_start_cancel(self.state.cntl.inner) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use std::thread;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    fn decode_nothing(_: *mut BrpcIOBuf) -> BrpcResult<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn response_future_fail() {
+        let mut future = ResponseFuture::new(decode_nothing);
+        future.fail(BrpcError::ESERIALIZE);
+        assert_eq!(Err(BrpcError::ESERIALIZE.into()), block_on(future));
+    }
+
+    #[test]
+    fn response_future_done_from_another_thread() {
+        let future = ResponseFuture::new(decode_nothing);
+        let (done, arg) = future.done_callback();
+        let arg = arg as usize;
+        let handle = thread::spawn(move || done(arg as *mut c_void));
+        assert_eq!(Ok(()), block_on(future));
+        handle.join().unwrap();
+    }
+}
@@ -36,20 +36,39 @@
 //! [README.md](https://github.com/mesalock-linux/brpc-rs/blob/master/README.md).
 
 mod channel;
+mod context;
 mod controller;
+mod future;
+mod responder;
+mod retry;
 mod server;
+mod unwind;
 
 mod error;
-pub use error::BrpcError;
+pub use error::{BrpcError, RpcStatus};
 
 #[doc(hidden)]
 pub type BrpcResult<T> = Result<T, BrpcError>;
 
+/// The result of an RPC, returned by server handlers and client stubs.
+pub type RpcResult<T> = Result<T, RpcStatus>;
+
 // for user code
-pub use channel::{Channel, ChannelOptions};
-pub use controller::Controller;
-pub use server::{Server, ServerOptions, Service, ServiceOwnership};
+pub use channel::{
+    CallMapper, Channel, ChannelBase, ChannelHandle, ChannelOptions, ChannelSslOptions,
+    ConnectionType, DynamicPartitionChannel, MergeResult, MockChannel, ParallelChannel,
+    ParallelChannelOptions, Partition, PartitionChannel, PartitionChannelOptions, PartitionParser,
+    Protocol, ResponseMerger, SelectiveChannel, SubCall, ToEndpoint,
+};
+pub use context::ServerContext;
+pub use controller::{CompressType, Controller};
+pub use future::ResponseFuture;
+pub use responder::Responder;
+pub use retry::RetryPolicy;
+pub use server::{Limiter, Server, ServerOptions, ServerSslOptions, Service, ShutdownHandle};
 
 // for protoc-generated code
 #[doc(hidden)]
 pub use brpc_sys as internal;
+#[doc(hidden)]
+pub use unwind::catch_handler_panic;
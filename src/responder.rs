@@ -0,0 +1,104 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::controller;
+use crate::{BrpcError, BrpcResult, RpcResult, RpcStatus, ServerContext};
+use brpc_sys::ffi::{self, BrpcClosure, BrpcController, BrpcIOBuf, BrpcMessage};
+use brpc_sys::zero_copy::IOBuf;
+use std::marker::PhantomData;
+use std::ptr;
+use std::thread;
+
+/// A `Responder` sends the response of an RPC being served. It is handed to
+/// asynchronous handlers, which may keep it, move it to another thread and
+/// respond later without blocking a BRPC worker. The RPC finishes when
+/// `respond()` is called; dropping a `Responder` without responding fails the
+/// RPC, with `BrpcError::EPANIC` if it is dropped by a panic.
+pub struct Responder<T> {
+    ctx: ServerContext,
+    response: *mut BrpcMessage,
+    done: *mut BrpcClosure,
+    encode: fn(&T, *mut BrpcIOBuf) -> BrpcResult<()>,
+    _marker: PhantomData<fn(T)>,
+}
+
+// BRPC allows an RPC to be finished from any thread.
+unsafe impl<T> Send for Responder<T> {}
+
+impl<T> Responder<T> {
+    #[doc(hidden)]
+    pub unsafe fn from_raw(
+        cntl: *mut BrpcController,
+        response: *mut BrpcMessage,
+        done: *mut BrpcClosure,
+        encode: fn(&T, *mut BrpcIOBuf) -> BrpcResult<()>,
+    ) -> Self {
+        Responder {
+            ctx: ServerContext::from_raw(cntl),
+            response,
+            done,
+            encode,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Information about the RPC being served.
+    pub fn context(&self) -> &ServerContext {
+        &self.ctx
+    }
+
+    /// Information about the RPC being served, which sets e.g. the response
+    /// attachment.
+    pub fn context_mut(&mut self) -> &mut ServerContext {
+        &mut self.ctx
+    }
+
+    /// Finish the RPC with `result`. An `Err` fails the RPC, and its code and
+    /// message are sent to the client.
+    pub fn respond(mut self, result: RpcResult<T>) {
+        self.finish(result);
+    }
+
+    fn finish(&mut self, result: RpcResult<T>) {
+        if self.done.is_null() {
+            return;
+        }
+        let encoded = result.and_then(|response| {
+            let buf = IOBuf::new();
+            (self.encode)(&response, buf.as_ptr())?;
+            if 0 == unsafe { ffi::brpc_message_parse_delimited(self.response, buf.as_ptr()) } {
+                return Err(BrpcError::ESERIALIZE.into());
+            }
+            Ok(())
+        });
+        if let Err(status) = encoded {
+            controller::set_failed(self.ctx.cntl, &status);
+        }
+        unsafe { ffi::brpc_closure_run(self.done) };
+        self.done = ptr::null_mut();
+    }
+}
+
+impl<T> Drop for Responder<T> {
+    fn drop(&mut self) {
+        let status = if thread::panicking() {
+            RpcStatus::new(BrpcError::EPANIC as i32, "handler panicked")
+        } else {
+            RpcStatus::new(
+                BrpcError::EINTERNAL as i32,
+                "responder dropped without responding",
+            )
+        };
+        self.finish(Err(status));
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::unwind::catch_panic;
+use crate::Controller;
+use brpc_sys::ffi::{self, BrpcController, BrpcRetryPolicy};
+use libc::{c_int, c_void};
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Decides whether a failed RPC is retried, replacing the default policy of
+/// BRPC, which retries e.g. connection failures but never application errors.
+/// Set it with `ChannelOptions::set_retry_policy()` or per call with
+/// `Controller::set_retry_policy()`. A policy is only consulted while the
+/// retry limit, see `ChannelOptions::set_max_retry()`, is not reached.
+pub trait RetryPolicy: Send + Sync {
+    /// Whether to retry the failed RPC of `cntl`. `cntl.status()` tells why
+    /// it failed and how many times it was retried.
+    fn do_retry(&self, cntl: &Controller) -> bool;
+
+    /// How long to wait before retrying the RPC of `cntl`. The retry is sent
+    /// right away if the RPC would time out in the meantime. The default is no
+    /// wait. Calls of client stubs are backed off, while the sub-calls of a
+    /// `ParallelChannel` or a `PartitionChannel` are retried right away.
+    fn backoff(&self, _cntl: &Controller) -> Duration {
+        Duration::from_millis(0)
+    }
+}
+
+impl<T: RetryPolicy + ?Sized> RetryPolicy for Box<T> {
+    fn do_retry(&self, cntl: &Controller) -> bool {
+        (**self).do_retry(cntl)
+    }
+
+    fn backoff(&self, cntl: &Controller) -> Duration {
+        (**self).backoff(cntl)
+    }
+}
+
+impl<T: RetryPolicy + ?Sized> RetryPolicy for Arc<T> {
+    fn do_retry(&self, cntl: &Controller) -> bool {
+        (**self).do_retry(cntl)
+    }
+
+    fn backoff(&self, cntl: &Controller) -> Duration {
+        (**self).backoff(cntl)
+    }
+}
+
+// A brpc::RetryPolicy calling a Rust `RetryPolicy`, shared by the channels
+// or the call it is set on.
+pub(crate) struct RetryPolicyHandle {
+    pub(crate) inner: *mut BrpcRetryPolicy,
+}
+
+// The policy itself is `Send + Sync`
+unsafe impl Send for RetryPolicyHandle {}
+unsafe impl Sync for RetryPolicyHandle {}
+
+impl RetryPolicyHandle {
+    pub(crate) fn new<P: RetryPolicy + 'static>(policy: P) -> Arc<Self> {
+        let policy: Box<dyn RetryPolicy> = Box::new(policy);
+        Arc::new(RetryPolicyHandle {
+            inner: unsafe {
+                ffi::brpc_retry_policy_new(
+                    do_retry,
+                    Box::into_raw(Box::new(policy)) as *mut c_void,
+                    drop_retry_policy,
+                )
+            },
+        })
+    }
+}
+
+impl Drop for RetryPolicyHandle {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_retry_policy_destroy(self.inner);
+        }
+    }
+}
+
+// The controller of the RPC being retried, which is owned by BRPC
+fn borrow_controller(cntl: *mut BrpcController) -> ManuallyDrop<Controller> {
+    ManuallyDrop::new(unsafe { Controller::from_raw(cntl) })
+}
+
+// Microseconds to wait before a retry, none if the RPC would time out in the
+// meantime. `remaining_us` is -1 without a deadline.
+fn backoff_us(backoff: Duration, remaining_us: i64) -> i64 {
+    let backoff_us = backoff.as_micros().min(i64::MAX as u128) as i64;
+    if remaining_us >= 0 && backoff_us >= remaining_us {
+        0
+    } else {
+        backoff_us
+    }
+}
+
+// The call is issued again by BRPC right away, or by the client stub once
+// `wait_us` has elapsed
+extern "C" fn do_retry(arg: *mut c_void, cntl: *mut BrpcController, wait_us: *mut i64) -> c_int {
+    let policy = unsafe { &*(arg as *const Box<dyn RetryPolicy>) };
+    let cntl = borrow_controller(cntl);
+    let retry = catch_panic(|| Ok(policy.do_retry(&cntl))).unwrap_or(false);
+    let mut backoff = Duration::from_millis(0);
+    if retry {
+        backoff = catch_panic(|| Ok(policy.backoff(&cntl))).unwrap_or_default();
+    }
+    let remaining_us = unsafe { ffi::brpc_controller_remaining_deadline_us(cntl.inner) };
+    unsafe { *wait_us = backoff_us(backoff, remaining_us) };
+    retry as c_int
+}
+
+extern "C" fn drop_retry_policy(arg: *mut c_void) {
+    drop(unsafe { Box::from_raw(arg as *mut Box<dyn RetryPolicy>) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingPolicy(Arc<AtomicUsize>);
+
+    impl RetryPolicy for CountingPolicy {
+        fn do_retry(&self, cntl: &Controller) -> bool {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            cntl.retried_count() < 1
+        }
+
+        fn backoff(&self, _cntl: &Controller) -> Duration {
+            Duration::from_millis(20)
+        }
+    }
+
+    #[test]
+    fn retry_policy_callbacks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let policy: Box<dyn RetryPolicy> = Box::new(CountingPolicy(calls.clone()));
+        let arg = Box::into_raw(Box::new(policy)) as *mut c_void;
+        let cntl = Controller::new();
+        let mut backoff_us = -1;
+        assert_eq!(1, do_retry(arg, cntl.inner, &mut backoff_us));
+        assert_eq!(20_000, backoff_us);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        drop_retry_policy(arg);
+        assert_eq!(1, Arc::strong_count(&calls));
+    }
+
+    #[test]
+    fn retry_backoff_within_deadline() {
+        let backoff = Duration::from_millis(20);
+        assert_eq!(20_000, backoff_us(backoff, -1));
+        assert_eq!(20_000, backoff_us(backoff, 30_000));
+        // The RPC would time out before the retry
+        assert_eq!(0, backoff_us(backoff, 20_000));
+        assert_eq!(0, backoff_us(backoff, 0));
+        assert_eq!(0, backoff_us(Duration::from_millis(0), -1));
+    }
+
+    struct PanickingPolicy;
+
+    impl RetryPolicy for PanickingPolicy {
+        fn do_retry(&self, _cntl: &Controller) -> bool {
+            panic!("do_retry");
+        }
+    }
+
+    struct PanickingBackoff;
+
+    impl RetryPolicy for PanickingBackoff {
+        fn do_retry(&self, _cntl: &Controller) -> bool {
+            true
+        }
+
+        fn backoff(&self, _cntl: &Controller) -> Duration {
+            panic!("backoff");
+        }
+    }
+
+    #[test]
+    fn panicking_retry_policy() {
+        let cntl = Controller::new();
+        let policy: Box<dyn RetryPolicy> = Box::new(PanickingPolicy);
+        let arg = Box::into_raw(Box::new(policy)) as *mut c_void;
+        let mut backoff_us = -1;
+        assert_eq!(0, do_retry(arg, cntl.inner, &mut backoff_us));
+        assert_eq!(0, backoff_us);
+        drop_retry_policy(arg);
+
+        // Retried right away
+        let policy: Box<dyn RetryPolicy> = Box::new(PanickingBackoff);
+        let arg = Box::into_raw(Box::new(policy)) as *mut c_void;
+        let mut backoff_us = -1;
+        assert_eq!(1, do_retry(arg, cntl.inner, &mut backoff_us));
+        assert_eq!(0, backoff_us);
+        drop_retry_policy(arg);
+    }
+
+    #[test]
+    fn retry_policy_handle_owns_policy() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handle = RetryPolicyHandle::new(CountingPolicy(calls.clone()));
+        assert_ne!(handle.inner, std::ptr::null_mut());
+        assert_eq!(2, Arc::strong_count(&calls));
+        drop(handle);
+        assert_eq!(1, Arc::strong_count(&calls));
+    }
+}
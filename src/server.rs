@@ -12,55 +12,113 @@
 // limitations under the License.
 
 use crate::{BrpcError, BrpcResult};
-use brpc_sys::ffi::{self, BrpcServer, BrpcServerOptions};
-use libc::{c_int, c_void};
-
-#[repr(C)]
-/// Represent server's ownership of services.
-pub enum ServiceOwnership {
-    ServerOwnsService = 0,
-    ServerDoesntOwnService = 1,
-}
+use brpc_sys::ffi::{self, BrpcServer, BrpcServerOptions, BrpcServerSslOptions};
+use libc::{c_char, c_int, c_void};
+use std::ffi::CString;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 
 #[doc(hidden)]
-pub trait Service {
+pub trait Service: Send + Sync {
     fn get_service_ptr(&self) -> *mut c_void;
 }
 
+impl<T: Service + ?Sized> Service for Box<T> {
+    fn get_service_ptr(&self) -> *mut c_void {
+        (**self).get_service_ptr()
+    }
+}
+
+impl<T: Service + ?Sized> Service for Arc<T> {
+    fn get_service_ptr(&self) -> *mut c_void {
+        (**self).get_service_ptr()
+    }
+}
+
+// Shared between a `Server` and its `ShutdownHandle`s, which may outlive it.
+struct ShutdownState {
+    server: Mutex<*mut BrpcServer>, // null once the server is dropped
+    stopped: Condvar,
+}
+
+// brpc::Server::Stop() may be called from any thread.
+unsafe impl Send for ShutdownState {}
+unsafe impl Sync for ShutdownState {}
+
 /// A `Server` provides a BRPC server where multiple BRPC services can run.
 pub struct Server {
     inner: *mut BrpcServer, // brpc_server_t in ffi.cpp
+    services: Vec<Box<dyn Service>>,
+    shutdown: Arc<ShutdownState>,
 }
 
+// Services are `Send + Sync`, and brpc::Server is thread-safe.
+unsafe impl Send for Server {}
+unsafe impl Sync for Server {}
+
 impl Server {
     /// Create a new `Server`
     pub fn new() -> Self {
+        let inner = unsafe { ffi::brpc_server_new() };
         Server {
-            inner: unsafe { ffi::brpc_server_new() },
+            inner,
+            services: Vec::new(),
+            shutdown: Arc::new(ShutdownState {
+                server: Mutex::new(inner),
+                stopped: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Add a `Service`, which can be passed by value, in a `Box` or in an
+    /// `Arc`. The server takes ownership of the service and keeps it alive
+    /// until the server has been stopped and dropped.
+    pub fn add_service<T: Service + 'static>(&mut self, service: T) -> BrpcResult<()> {
+        let ret = unsafe { ffi::brpc_server_add_service(self.inner, service.get_service_ptr()) };
+        if ret == 0 {
+            self.services.push(Box::new(service));
+            Ok(())
+        } else {
+            Err(BrpcError::EINTERNAL)
         }
     }
 
-    /// Add a `Service`. `ownership` represents server's ownership of services.
-    /// If `ownership` is `SERVER_OWNS_SERVICE`, server deletes the service at
-    /// destruction. To prevent the deletion, set ownership to
-    /// `SERVER_DOESNT_OWN_SERVICE`.
-    pub fn add_service<T: Service + Sized>(
+    /// Limit concurrent calls to `method_name` of the service named
+    /// `service_name`, e.g. `"EchoService"` or `"example.EchoService"`, with
+    /// `limiter`. Calls beyond the limit fail with `BrpcError::ELIMIT`. Must
+    /// be called after the service is added and before the server is started.
+    pub fn set_method_max_concurrency(
         &mut self,
-        service: &T,
-        ownership: ServiceOwnership,
+        service_name: &str,
+        method_name: &str,
+        limiter: Limiter,
     ) -> BrpcResult<()> {
+        let service_name = CString::new(service_name).map_err(|_| BrpcError::ENOSERVICE)?;
+        let method_name = CString::new(method_name).map_err(|_| BrpcError::ENOMETHOD)?;
+        let max_concurrency = match limiter {
+            Limiter::Constant(n) => n.min(i32::MAX as u32) as c_int,
+            Limiter::Auto => -1,
+        };
         let ret = unsafe {
-            ffi::brpc_server_add_service(self.inner, service.get_service_ptr(), ownership as c_int)
+            ffi::brpc_server_set_method_max_concurrency(
+                self.inner,
+                service_name.as_ptr(),
+                method_name.as_ptr(),
+                max_concurrency,
+            )
         };
         if ret == 0 {
             Ok(())
         } else {
-            Err(BrpcError::EINTERNAL)
+            Err(BrpcError::from(ret))
         }
     }
 
-    /// Config a `Server` with the provided TCP port and `ServerOptions`.
+    /// Config a `Server` with the provided TCP port and `ServerOptions`. On
+    /// port 0 the system picks a free port, see `listen_port()`.
     pub fn start(&mut self, port: u16, opt: &ServerOptions) -> BrpcResult<()> {
+        unsafe { ffi::brpc_server_set_abort_on_panic(self.inner, opt.abort_on_panic as c_int) };
         let ret = unsafe { ffi::brpc_server_start(self.inner, i32::from(port), opt.inner) };
         if ret == 0 {
             Ok(())
@@ -69,30 +127,117 @@ impl Server {
         }
     }
 
-    /// Run a `Server` until asked to quit (e.g CTRL-C).
+    /// The TCP port the `Server` listens on once started, 0 before.
+    pub fn listen_port(&self) -> u16 {
+        unsafe { ffi::brpc_server_listen_port(self.inner) as u16 }
+    }
+
+    /// Run a `Server` until asked to quit (e.g CTRL-C) or shut down through a
+    /// `ShutdownHandle`, then wait for in-flight requests to finish.
     pub fn run(&mut self) {
-        unsafe { ffi::brpc_server_run_until_asked_to_quit(self.inner) };
+        let mut server = self.shutdown.server.lock().unwrap();
+        while self.is_running() && unsafe { ffi::brpc_is_asked_to_quit() } == 0 {
+            server = self
+                .shutdown
+                .stopped
+                .wait_timeout(server, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+        drop(server);
+        let _ = self.stop(0);
+        let _ = self.join();
+    }
+
+    /// Stop accepting new requests. `closewait_ms` is passed to
+    /// `brpc::Server::Stop()`. Requests being served are not interrupted,
+    /// call `join()` to wait for them.
+    pub fn stop(&self, closewait_ms: i32) -> BrpcResult<()> {
+        let ret = unsafe { ffi::brpc_server_stop(self.inner, closewait_ms as c_int) };
+        self.shutdown.stopped.notify_all();
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(BrpcError::EINTERNAL)
+        }
+    }
+
+    /// Wait until a stopped `Server` has finished all in-flight requests.
+    pub fn join(&self) -> BrpcResult<()> {
+        let ret = unsafe { ffi::brpc_server_join(self.inner) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(BrpcError::EINTERNAL)
+        }
+    }
+
+    /// Whether the `Server` has been started and not stopped yet.
+    pub fn is_running(&self) -> bool {
+        unsafe { 0 != ffi::brpc_server_is_running(self.inner) }
+    }
+
+    /// Get a `ShutdownHandle` which stops this `Server` from anywhere.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: self.shutdown.clone(),
+        }
     }
 }
 
 impl Drop for Server {
     fn drop(&mut self) {
+        // No RPC may be running when `services` is dropped afterwards.
+        let _ = self.stop(0);
+        let _ = self.join();
+        *self.shutdown.server.lock().unwrap() = ptr::null_mut();
         unsafe {
             ffi::brpc_server_destroy(self.inner);
         }
     }
 }
 
+/// A `ShutdownHandle` stops a `Server` from another thread, e.g. a signal
+/// handling thread. It can be cloned and may outlive the `Server`, in which
+/// case `shutdown()` does nothing.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    /// Stop the `Server` from accepting new requests. A `Server` blocked in
+    /// `run()` returns after in-flight requests have finished.
+    pub fn shutdown(&self) {
+        let server = self.state.server.lock().unwrap();
+        if !server.is_null() {
+            unsafe { ffi::brpc_server_stop(*server, 0) };
+        }
+        self.state.stopped.notify_all();
+    }
+}
+
 impl Default for Server {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Concurrency limiter of a method, see `Server::set_method_max_concurrency()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limiter {
+    /// At most this many concurrent calls. 0 means unlimited.
+    Constant(u32),
+    /// A limit adjusted to the latency and the throughput measured by the
+    /// server, which rejects calls before they pile up.
+    Auto,
+}
+
 /// Options for a `Server`
 pub struct ServerOptions {
     #[doc(hidden)]
     pub(crate) inner: *mut BrpcServerOptions, // brpc_server_options_t in ffi.cpp
+    abort_on_panic: bool,
 }
 
 impl ServerOptions {
@@ -100,14 +245,45 @@ impl ServerOptions {
     pub fn new() -> Self {
         ServerOptions {
             inner: unsafe { ffi::brpc_server_options_new() },
+            abort_on_panic: false,
         }
     }
 
+    /// Abort the process when a handler panics. By default, the panic is
+    /// logged and only fails the RPC being served with `BrpcError::EPANIC`.
+    /// Only handlers of this `Server` abort, panics in client callbacks such
+    /// as a `RetryPolicy` never do.
+    pub fn set_abort_on_panic(&mut self, abort: bool) {
+        self.abort_on_panic = abort;
+    }
+
     /// Notify user when there's no data for at least `idle_timeout_ms`
     /// milliseconds. The default value is -1.
     pub fn set_idle_timeout_ms(&mut self, timeout: i32) {
         unsafe { ffi::brpc_server_options_set_idle_timeout_ms(self.inner, timeout as c_int) }
     }
+
+    /// Set the number of worker threads. Workers are shared by all servers and
+    /// channels in the process, so this is a hint of the minimum. <=0 leaves
+    /// the number unchanged, which is the number of CPU cores by default.
+    pub fn set_num_threads(&mut self, num_threads: i32) {
+        unsafe { ffi::brpc_server_options_set_num_threads(self.inner, num_threads as c_int) }
+    }
+
+    /// Set the max number of concurrent calls to all methods of the server.
+    /// Calls beyond the limit fail with `BrpcError::ELIMIT`. <=0 means
+    /// unlimited, which is the default.
+    pub fn set_max_concurrency(&mut self, max_concurrency: i32) {
+        unsafe {
+            ffi::brpc_server_options_set_max_concurrency(self.inner, max_concurrency as c_int)
+        }
+    }
+
+    /// Accept TLS connections with `ssl_options`, along with plaintext ones.
+    /// A `Server` does not accept TLS by default.
+    pub fn set_ssl_options(&mut self, ssl_options: &ServerSslOptions) {
+        unsafe { ffi::brpc_server_options_set_ssl_options(self.inner, ssl_options.inner) }
+    }
 }
 
 impl Drop for ServerOptions {
@@ -124,10 +300,119 @@ impl Default for ServerOptions {
     }
 }
 
+/// TLS options of a `Server`, see `ServerOptions::set_ssl_options()`.
+/// Certificates and private keys are either PEM file paths or PEM strings.
+/// Setters taking strings fail with `BrpcError::EARGUMENT` if they contain a
+/// NUL byte.
+pub struct ServerSslOptions {
+    #[doc(hidden)]
+    pub inner: *mut BrpcServerSslOptions,
+}
+
+impl ServerSslOptions {
+    /// Make a `ServerSslOptions` with default values. A default certificate
+    /// must be set before the options are used.
+    pub fn new() -> Self {
+        ServerSslOptions {
+            inner: unsafe { ffi::brpc_server_ssl_options_new() },
+        }
+    }
+
+    /// Set the certificate presented to clients whose SNI name matches none
+    /// of the certificates added by `add_sni_certificate()`.
+    pub fn set_default_certificate(
+        &mut self,
+        certificate: &str,
+        private_key: &str,
+    ) -> BrpcResult<()> {
+        let certificate = CString::new(certificate).map_err(|_| BrpcError::EARGUMENT)?;
+        let private_key = CString::new(private_key).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe {
+            ffi::brpc_server_ssl_options_set_default_cert(
+                self.inner,
+                certificate.as_ptr(),
+                private_key.as_ptr(),
+            )
+        };
+        Ok(())
+    }
+
+    /// Present `certificate` to clients whose SNI name matches one of
+    /// `sni_filters`, such as `"api.example.com"` or `"*.example.com"`. The
+    /// domain names in the certificate are matched if `sni_filters` is empty.
+    pub fn add_sni_certificate(
+        &mut self,
+        certificate: &str,
+        private_key: &str,
+        sni_filters: &[&str],
+    ) -> BrpcResult<()> {
+        let certificate = CString::new(certificate).map_err(|_| BrpcError::EARGUMENT)?;
+        let private_key = CString::new(private_key).map_err(|_| BrpcError::EARGUMENT)?;
+        let filters = sni_filters
+            .iter()
+            .map(|filter| CString::new(*filter))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| BrpcError::EARGUMENT)?;
+        let filter_ptrs = filters
+            .iter()
+            .map(|f| f.as_ptr())
+            .collect::<Vec<*const c_char>>();
+        unsafe {
+            ffi::brpc_server_ssl_options_add_sni_cert(
+                self.inner,
+                certificate.as_ptr(),
+                private_key.as_ptr(),
+                filter_ptrs.as_ptr(),
+                filter_ptrs.len(),
+            )
+        };
+        Ok(())
+    }
+
+    /// Require clients to present a certificate signed by one of the CAs in
+    /// the PEM file `ca_file_path`, with a chain of at most `verify_depth`
+    /// certificates. Client certificates are NOT requested by default. The
+    /// verified subject is available in `ServerContext::peer_certificate_subject()`.
+    pub fn require_client_certificate(
+        &mut self,
+        ca_file_path: &str,
+        verify_depth: i32,
+    ) -> BrpcResult<()> {
+        let path = CString::new(ca_file_path).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe {
+            ffi::brpc_server_ssl_options_set_ca_file(self.inner, path.as_ptr());
+            ffi::brpc_server_ssl_options_set_verify_depth(self.inner, verify_depth as c_int);
+        }
+        Ok(())
+    }
+
+    /// Set the allowed cipher suites in OpenSSL cipher list format, such as
+    /// `"ECDHE-RSA-AES256-GCM-SHA384"`.
+    pub fn set_ciphers(&mut self, ciphers: &str) -> BrpcResult<()> {
+        let ciphers = CString::new(ciphers).map_err(|_| BrpcError::EARGUMENT)?;
+        unsafe { ffi::brpc_server_ssl_options_set_ciphers(self.inner, ciphers.as_ptr()) };
+        Ok(())
+    }
+}
+
+impl Drop for ServerSslOptions {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::brpc_server_ssl_options_destroy(self.inner);
+        }
+    }
+}
+
+impl Default for ServerSslOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::ptr;
+    use std::thread;
 
     struct NullService {}
     impl Service for NullService {
@@ -148,6 +433,67 @@ mod tests {
         opt.set_idle_timeout_ms(0);
     }
 
+    #[test]
+    fn server_options_set_concurrency() {
+        let mut opt = ServerOptions::new();
+        opt.set_num_threads(4);
+        opt.set_max_concurrency(100);
+    }
+
+    #[test]
+    fn server_set_method_max_concurrency_of_unknown_service() {
+        let mut server = Server::new();
+        assert_eq!(
+            Err(BrpcError::ENOSERVICE),
+            server.set_method_max_concurrency("EchoService", "echo", Limiter::Constant(1))
+        );
+        assert_eq!(
+            Err(BrpcError::ENOSERVICE),
+            server.set_method_max_concurrency("EchoService", "echo", Limiter::Auto)
+        );
+    }
+
+    #[test]
+    fn server_options_set_ssl_options() {
+        let mut ssl = ServerSslOptions::new();
+        assert_ne!(ssl.inner, ptr::null_mut());
+        ssl.set_default_certificate("server.crt", "server.key")
+            .unwrap();
+        ssl.add_sni_certificate(
+            "api.crt",
+            "api.key",
+            &["api.example.com", "*.api.example.com"],
+        )
+        .unwrap();
+        ssl.add_sni_certificate("other.crt", "other.key", &[])
+            .unwrap();
+        ssl.require_client_certificate("ca.pem", 1).unwrap();
+        ssl.set_ciphers("ECDHE-RSA-AES256-GCM-SHA384").unwrap();
+        let err = Some(BrpcError::EARGUMENT);
+        assert_eq!(
+            err,
+            ssl.set_default_certificate("server\0.crt", "server.key")
+                .err()
+        );
+        assert_eq!(
+            err,
+            ssl.add_sni_certificate("api.crt", "api.key", &["api\0"])
+                .err()
+        );
+        assert_eq!(err, ssl.require_client_certificate("ca\0.pem", 1).err());
+        assert_eq!(err, ssl.set_ciphers("\0").err());
+        let mut opt = ServerOptions::new();
+        opt.set_ssl_options(&ssl);
+    }
+
+    #[test]
+    fn server_options_set_abort_on_panic() {
+        let mut opt = ServerOptions::new();
+        assert!(!opt.abort_on_panic);
+        opt.set_abort_on_panic(true);
+        assert!(opt.abort_on_panic);
+    }
+
     #[test]
     fn server_new() {
         let server = Server::new();
@@ -158,18 +504,66 @@ mod tests {
     fn server_add_null_service() {
         let service = NullService {};
         let mut server = Server::new();
-        let ret = server.add_service(&service, ServiceOwnership::ServerDoesntOwnService);
+        let ret = server.add_service(service);
         assert_eq!(false, ret.is_ok()); // NullService must fail to add
+        assert!(server.services.is_empty());
+    }
+
+    #[test]
+    fn server_add_boxed_null_service() {
+        let service: Box<dyn Service> = Box::new(NullService {});
+        let mut server = Server::new();
+        assert!(server.add_service(service).is_err());
+    }
+
+    #[test]
+    fn server_add_shared_null_service() {
+        let service = Arc::new(NullService {});
+        let mut server = Server::new();
+        assert!(server.add_service(service.clone()).is_err());
+        assert_eq!(1, Arc::strong_count(&service));
     }
 
     #[test]
     fn server_start_null_service() {
         let service = NullService {};
         let mut server = Server::new();
-        let _ = server.add_service(&service, ServiceOwnership::ServerDoesntOwnService);
+        let _ = server.add_service(service);
 
         let opt = ServerOptions::new();
-        let ret = server.start(50000, &opt);
+        let ret = server.start(0, &opt);
         assert_eq!(true, ret.is_ok()); // NullService must fail to add
+        assert_ne!(0, server.listen_port());
+    }
+
+    #[test]
+    fn server_stop_and_join() {
+        let mut server = Server::new();
+        assert!(!server.is_running());
+        assert_eq!(0, server.listen_port());
+        server.start(0, &ServerOptions::new()).unwrap();
+        assert!(server.is_running());
+        server.stop(0).unwrap();
+        server.join().unwrap();
+        assert!(!server.is_running());
+    }
+
+    #[test]
+    fn server_run_until_shutdown() {
+        let mut server = Server::new();
+        server.start(0, &ServerOptions::new()).unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || handle.shutdown());
+        server.run();
+        assert!(!server.is_running());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown_handle_outlives_server() {
+        let server = Server::new();
+        let handle = server.shutdown_handle();
+        drop(server);
+        handle.clone().shutdown();
     }
 }
@@ -0,0 +1,89 @@
+// Copyright 2019 Baidu, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{BrpcError, RpcResult, RpcStatus};
+use brpc_sys::ffi::{self, BrpcController};
+use std::any::Any;
+use std::ffi::CString;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<Any>"
+    }
+}
+
+// Log a caught panic and turn it into a `BrpcError::EPANIC` status
+fn panic_status(payload: &(dyn Any + Send)) -> RpcStatus {
+    let message = format!("handler panicked: {}", panic_message(payload));
+    // Logged with BRPC, up to the first NUL if any
+    let text = message.split('\0').next().unwrap_or_default();
+    let text = CString::new(text).unwrap();
+    unsafe { ffi::brpc_log_error(text.as_ptr()) };
+    RpcStatus::new(BrpcError::EPANIC as i32, &message)
+}
+
+// Run a callback called from C++, which must not unwind into C++. A panic is
+// logged and turned into a `BrpcError::EPANIC` status, and never aborts the
+// process.
+pub(crate) fn catch_panic<T, F: FnOnce() -> RpcResult<T>>(f: F) -> RpcResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| Err(panic_status(&*payload)))
+}
+
+/// Run the handler of the RPC of `cntl` like `catch_panic()`, but abort the
+/// process on a panic if the `Server` serving the RPC is set to.
+#[doc(hidden)]
+pub unsafe fn catch_handler_panic<T, F: FnOnce() -> RpcResult<T>>(
+    cntl: *mut BrpcController,
+    f: F,
+) -> RpcResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let status = panic_status(&*payload);
+        if 0 != ffi::brpc_controller_abort_on_panic(cntl) {
+            process::abort();
+        }
+        Err(status)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Controller;
+
+    #[test]
+    fn catch_panic_ok() {
+        assert_eq!(Ok(1), catch_panic(|| Ok(1)));
+    }
+
+    #[test]
+    fn catch_panic_message() {
+        let result: RpcResult<()> = catch_panic(|| panic!("boom {}", 1));
+        let status = result.unwrap_err();
+        assert_eq!(BrpcError::EPANIC, status.error());
+        assert_eq!("handler panicked: boom 1", status.message);
+    }
+
+    #[test]
+    fn catch_handler_panic_without_server() {
+        // An RPC not served by a `Server` never aborts
+        let cntl = Controller::new();
+        let result: RpcResult<()> = unsafe { catch_handler_panic(cntl.inner, || panic!("boom")) };
+        assert_eq!(BrpcError::EPANIC, result.unwrap_err().error());
+    }
+}